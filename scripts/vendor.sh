@@ -0,0 +1,47 @@
+#!/bin/sh
+# Downloads pinned, prebuilt releases of the JavaScript libraries that are vendored as plain
+# directories under static/vendor, keeping only the files that are served to the browser.
+#
+# The whole static directory is embedded in the binary, so nothing else from the packages should
+# be copied. Run from the root of the repository after changing a version, then commit the
+# changes to static/vendor. The versions must match those in src/service.rs, which loads the same
+# releases from jsDelivr in builds where this script has not been run.
+
+set -eu
+
+KATEX_VERSION=0.16.9
+# The client relies on the promise-based mermaid.render, which was added in mermaid 10.
+MERMAID_VERSION=10.9.1
+
+vendor=static/vendor
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+# Downloads and extracts version $2 of the npm package $1 into $tmp/$1, printing the checksum of
+# the package so that it can be compared against the registry.
+fetch() {
+    curl -fsSL -o "$tmp/$1.tgz" "https://registry.npmjs.org/$1/-/$1-$2.tgz"
+    sha256sum "$tmp/$1.tgz"
+    mkdir -p "$tmp/$1"
+    tar -xzf "$tmp/$1.tgz" -C "$tmp/$1" --strip-components=1
+}
+
+fetch katex "$KATEX_VERSION"
+rm -rf "$vendor/katex"
+mkdir -p "$vendor/katex/dist/contrib" "$vendor/katex/dist/fonts"
+cp "$tmp/katex/dist/katex.min.js" "$tmp/katex/dist/katex.min.css" "$vendor/katex/dist/"
+cp "$tmp/katex/dist/contrib/auto-render.min.js" "$vendor/katex/dist/contrib/"
+# Every browser that runs the client supports WOFF2, the first format listed by katex.min.css.
+cp "$tmp"/katex/dist/fonts/*.woff2 "$vendor/katex/dist/fonts/"
+cp "$tmp/katex/LICENSE" "$vendor/katex/"
+echo "$KATEX_VERSION" >"$vendor/katex/VERSION"
+
+fetch mermaid "$MERMAID_VERSION"
+rm -rf "$vendor/mermaid"
+mkdir -p "$vendor/mermaid/dist"
+cp "$tmp/mermaid/dist/mermaid.min.js" "$vendor/mermaid/dist/"
+# The package may not include the license of the monorepo that it is built from.
+if [ -f "$tmp/mermaid/LICENSE" ]; then
+    cp "$tmp/mermaid/LICENSE" "$vendor/mermaid/"
+fi
+echo "$MERMAID_VERSION" >"$vendor/mermaid/VERSION"
@@ -0,0 +1,213 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::{service, Config, Error, ExternalRenderer, MathDelimiter, Renderer, Result, Server};
+
+/// Configures a [`Server`] before binding it.
+///
+/// All options are validated by [`bind`][Self::bind], so misconfiguration is reported before the
+/// server starts rather than on the first render.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn dox() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::net::SocketAddr;
+/// use aurelius::Server;
+///
+/// let addr = "127.0.0.1:1337".parse::<SocketAddr>()?;
+/// let server = Server::builder()
+///     .static_root("/path/to/markdown/dir")
+///     .highlight_theme("darcula")
+///     .bind(&addr)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    static_root: Option<PathBuf>,
+    highlight_theme: Option<String>,
+    custom_css: Vec<String>,
+    math: Option<bool>,
+    math_delimiters: Option<Vec<MathDelimiter>>,
+    mermaid: Option<bool>,
+    toc: Option<bool>,
+    renderer: Option<Arc<dyn Renderer>>,
+    render_timeout: Option<Duration>,
+    debounce: Option<Duration>,
+    shutdown_timeout: Option<Duration>,
+    raw_html_fallback: bool,
+}
+
+/// The default amount of time that [`Server::shutdown`] waits for clients to close.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl ServerBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        ServerBuilder::default()
+    }
+
+    /// Set the directory that static files will be served from.
+    ///
+    /// See [`Server::set_static_root`].
+    pub fn static_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.static_root = Some(root.into());
+        self
+    }
+
+    /// Set the highlight.js theme used for code blocks.
+    ///
+    /// See [`Server::set_highlight_theme`].
+    pub fn highlight_theme(mut self, theme: impl Into<String>) -> Self {
+        self.highlight_theme = Some(theme.into());
+        self
+    }
+
+    /// Set custom CSS links and files to be served with the rendered HTML.
+    ///
+    /// See [`Server::set_custom_css`].
+    pub fn custom_css(mut self, stylesheets: Vec<String>) -> Self {
+        self.custom_css = stylesheets;
+        self
+    }
+
+    /// Set whether math in the rendered HTML is rendered by KaTeX.
+    ///
+    /// See [`Server::set_math`].
+    pub fn math(mut self, enabled: bool) -> Self {
+        self.math = Some(enabled);
+        self
+    }
+
+    /// Set the delimiters that surround math in the rendered HTML.
+    ///
+    /// See [`Server::set_math_delimiters`].
+    pub fn math_delimiters(mut self, delimiters: Vec<MathDelimiter>) -> Self {
+        self.math_delimiters = Some(delimiters);
+        self
+    }
+
+    /// Set whether fenced code blocks with the `mermaid` language are rendered as diagrams.
+    ///
+    /// See [`Server::set_mermaid`].
+    pub fn mermaid(mut self, enabled: bool) -> Self {
+        self.mermaid = Some(enabled);
+        self
+    }
+
+    /// Set whether a table of contents is shown beside the preview.
+    ///
+    /// See [`Server::set_toc`].
+    pub fn toc(mut self, enabled: bool) -> Self {
+        self.toc = Some(enabled);
+        self
+    }
+
+    /// Set the renderer used to convert markdown to HTML.
+    ///
+    /// See [`Server::set_renderer`].
+    pub fn renderer(mut self, renderer: impl Renderer + 'static) -> Self {
+        self.renderer = Some(Arc::new(renderer));
+        self
+    }
+
+    /// Set an external program to use for rendering the markdown.
+    ///
+    /// See [`Server::set_external_renderer`].
+    pub fn external_renderer(self, command: Command) -> Self {
+        self.renderer(ExternalRenderer::new(command))
+    }
+
+    /// Set the maximum amount of time that a render may take.
+    ///
+    /// See [`Server::set_render_timeout`].
+    pub fn render_timeout(mut self, timeout: Duration) -> Self {
+        self.render_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the amount of time to wait for more markdown before rendering.
+    ///
+    /// See [`Server::set_debounce`].
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = Some(debounce);
+        self
+    }
+
+    /// Set whether clients that do not negotiate a protocol version are sent raw HTML.
+    ///
+    /// See [`Server::set_raw_html_fallback`].
+    pub fn raw_html_fallback(mut self, enabled: bool) -> Self {
+        self.raw_html_fallback = enabled;
+        self
+    }
+
+    /// Set the maximum amount of time that [`Server::shutdown`] waits for clients to close.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Validates the configuration and binds the server to a specified address.
+    ///
+    /// See [`Server::bind`].
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`Server::bind`], this method returns:
+    ///
+    /// - [`Error::Theme`] if the highlight theme does not exist.
+    /// - [`Error::Css`] if a custom CSS file could not be read.
+    /// - Any error returned by [`Renderer::validate`], such as [`Error::RendererSpawn`] if an
+    ///   external renderer's program could not be found.
+    pub async fn bind(self, addr: &SocketAddr) -> Result<Server> {
+        let mut config = Config::default();
+
+        if let Some(theme) = self.highlight_theme {
+            if !service::highlight_theme_exists(&theme) {
+                return Err(Error::Theme(theme));
+            }
+            config.highlight_theme = theme;
+        }
+
+        let (css_links, custom_styles) = crate::load_custom_css(&self.custom_css)?;
+        config.css_links = css_links;
+        config.custom_styles = custom_styles;
+        config.static_root = self.static_root;
+        if let Some(math) = self.math {
+            config.math = math;
+        }
+        if let Some(delimiters) = self.math_delimiters {
+            config.math_delimiters = delimiters;
+        }
+        if let Some(mermaid) = self.mermaid {
+            config.mermaid = mermaid;
+        }
+        if let Some(toc) = self.toc {
+            config.toc = toc;
+        }
+        config.render_timeout = self.render_timeout;
+        config.debounce = self.debounce;
+        config.raw_html_fallback = self.raw_html_fallback;
+
+        if let Some(renderer) = self.renderer {
+            renderer.validate()?;
+            config.renderer = renderer;
+        }
+
+        Server::start(
+            addr,
+            config,
+            self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+        )
+        .await
+    }
+}
@@ -0,0 +1,189 @@
+//! Diffing of rendered HTML at the granularity of top-level elements.
+//!
+//! Rather than sending the full HTML of every render, the server splits the HTML into its
+//! top-level elements ("blocks") and sends each client a [`Patch`] that replaces only the blocks
+//! that changed since the last render that the client received.
+
+/// Elements that have no closing tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whose contents are not parsed as HTML.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Replaces `delete` blocks starting at index `start` with the blocks in `insert`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Patch<'a> {
+    pub(crate) start: usize,
+    pub(crate) delete: usize,
+    pub(crate) insert: &'a [&'a str],
+}
+
+impl Patch<'_> {
+    /// Returns whether the patch leaves the blocks unchanged.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.delete == 0 && self.insert.is_empty()
+    }
+}
+
+/// Returns a patch that turns the `old` blocks into the `new` blocks.
+///
+/// Only the common prefix and suffix of the blocks are preserved, which is sufficient for the
+/// localized changes that are typical while editing.
+pub(crate) fn diff<'a>(old: &[impl AsRef<str>], new: &'a [&'a str]) -> Patch<'a> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old.as_ref() == **new)
+        .count();
+
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old.as_ref() == **new)
+        .count();
+
+    Patch {
+        start: prefix,
+        delete: old.len() - prefix - suffix,
+        insert: &new[prefix..new.len() - suffix],
+    }
+}
+
+/// Splits HTML into its top-level elements.
+///
+/// Returns `None` if the HTML contains top-level text other than whitespace, or if its tags are
+/// unbalanced, since the blocks would not correspond to the elements parsed by the client.
+pub(crate) fn split_blocks(html: &str) -> Option<Vec<&str>> {
+    let mut blocks = vec![];
+    let mut open = Vec::<&str>::new();
+    let mut block_start = 0;
+    let mut pos = 0;
+
+    while pos < html.len() {
+        let rest = &html[pos..];
+
+        if rest.starts_with("<!--") {
+            // Comments are not elements, so they may be skipped even at the top level.
+            pos += rest.find("-->")? + "-->".len();
+            continue;
+        }
+
+        let tag = match parse_tag(rest) {
+            Some(tag) => tag,
+            None => {
+                // A `<` that does not start a tag is text.
+                let skip = usize::from(rest.starts_with('<'));
+                let text_len = rest[skip..].find('<').map_or(rest.len(), |i| i + skip);
+                if open.is_empty() && !rest[..text_len].trim().is_empty() {
+                    return None;
+                }
+                pos += text_len;
+                continue;
+            }
+        };
+
+        if open.is_empty() {
+            block_start = pos;
+        }
+        pos += tag.len;
+
+        match tag.kind {
+            TagKind::Other => {
+                if open.is_empty() {
+                    return None;
+                }
+            }
+            TagKind::Close => {
+                if !open.pop()?.eq_ignore_ascii_case(tag.name) {
+                    return None;
+                }
+            }
+            TagKind::Open if tag.self_closing || is_one_of(tag.name, VOID_ELEMENTS) => {}
+            TagKind::Open if is_one_of(tag.name, RAW_TEXT_ELEMENTS) => {
+                let close = format!("</{}", tag.name.to_ascii_lowercase());
+                let end = html[pos..].to_ascii_lowercase().find(&close)?;
+                pos += end;
+                open.push(tag.name);
+                continue;
+            }
+            TagKind::Open => open.push(tag.name),
+        }
+
+        if open.is_empty() {
+            blocks.push(&html[block_start..pos]);
+        }
+    }
+
+    if !open.is_empty() {
+        return None;
+    }
+
+    Some(blocks)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TagKind {
+    Open,
+    Close,
+
+    /// A doctype or processing instruction.
+    Other,
+}
+
+#[derive(Debug)]
+struct Tag<'a> {
+    kind: TagKind,
+    name: &'a str,
+    self_closing: bool,
+
+    /// The length of the tag in bytes, including the angle brackets.
+    len: usize,
+}
+
+/// Parses the tag at the start of `html`, if there is one.
+fn parse_tag(html: &str) -> Option<Tag<'_>> {
+    let rest = html.strip_prefix('<')?;
+
+    let (kind, rest) = match rest.as_bytes().first()? {
+        b'/' => (TagKind::Close, &rest[1..]),
+        b'!' | b'?' => (TagKind::Other, &rest[1..]),
+        _ => (TagKind::Open, rest),
+    };
+
+    let name_len = rest
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(rest.len());
+    let name = &rest[..name_len];
+
+    if kind != TagKind::Other && !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    // Find the end of the tag, skipping over quoted attribute values.
+    let mut quote = None;
+    let end = html.char_indices().find_map(|(i, c)| {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(i),
+            None => {}
+        }
+        None
+    })?;
+
+    Some(Tag {
+        kind,
+        name,
+        self_closing: html[..end].ends_with('/'),
+        len: end + 1,
+    })
+}
+
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
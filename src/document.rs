@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Instant;
+
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch::{self, Receiver, Sender};
+use tokio::sync::Notify;
+use tracing::log::*;
+
+use crate::error::display_chain;
+use crate::protocol::ClientEvent;
+use crate::{Config, Error, Metadata, Result};
+
+/// A markdown document served by a [`Server`][crate::Server].
+///
+/// Each document is served at its own URL path, named after the document, with its own websocket
+/// channel. Documents are created with [`Server::document`][crate::Server::document]. A document
+/// remains available until all of its handles are dropped.
+#[derive(Debug, Clone)]
+pub struct Document {
+    state: Arc<DocumentState>,
+}
+
+impl Document {
+    pub(crate) fn new(state: Arc<DocumentState>) -> Self {
+        Document { state }
+    }
+
+    /// The name of the document. The document is served at `/<name>`.
+    pub fn name(&self) -> &str {
+        &self.state.name
+    }
+
+    /// Publish new markdown to be rendered for this document.
+    ///
+    /// See [`Server::send`][crate::Server::send].
+    pub async fn send(&self, markdown: &str) -> Result<()> {
+        self.state.send(markdown).await
+    }
+
+    /// Scroll connected clients to the element rendered from a line of the markdown.
+    ///
+    /// See [`Server::scroll_to_line`][crate::Server::scroll_to_line].
+    pub fn scroll_to_line(&self, line: usize) {
+        self.state.scroll_to_line(line);
+    }
+
+    /// Returns a stream of the events reported by this document's clients.
+    ///
+    /// See [`Server::events`][crate::Server::events].
+    pub fn events(&self) -> impl Stream<Item = ClientEvent> + Send + 'static {
+        let events_rx = self.state.events_tx.subscribe();
+
+        stream::unfold(events_rx, |mut events_rx| async move {
+            loop {
+                match events_rx.recv().await {
+                    Ok(event) => return Some((event, events_rx)),
+                    Err(RecvError::Lagged(n)) => warn!("dropped {} client events", n),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+/// The latest state of a document's preview, shared with websocket clients.
+#[derive(Debug, Default)]
+pub(crate) struct Preview {
+    /// Incremented whenever `html` changes.
+    pub(crate) revision: u64,
+
+    /// The last successfully rendered HTML.
+    pub(crate) html: String,
+
+    /// The metadata of the last successfully rendered markdown.
+    pub(crate) metadata: Option<Metadata>,
+
+    /// The error from the last render, if it failed.
+    pub(crate) error: Option<String>,
+}
+
+/// The number of scroll requests that are buffered for each websocket client.
+const SCROLL_CAPACITY: usize = 16;
+
+/// The number of client events that are buffered for each event stream.
+const EVENTS_CAPACITY: usize = 64;
+
+/// The channels connecting a websocket client to its document.
+#[derive(Debug)]
+pub(crate) struct Subscription {
+    pub(crate) preview_rx: Receiver<Preview>,
+    pub(crate) scroll_rx: broadcast::Receiver<usize>,
+    pub(crate) events_tx: broadcast::Sender<ClientEvent>,
+}
+
+/// The rendering state of a single document.
+#[derive(Debug)]
+pub(crate) struct DocumentState {
+    name: String,
+    config: Arc<RwLock<Config>>,
+    documents: Weak<Documents>,
+    generation: AtomicU64,
+    superseded: Notify,
+    output: Mutex<String>,
+    tx: Sender<Preview>,
+    scroll_tx: broadcast::Sender<usize>,
+    events_tx: broadcast::Sender<ClientEvent>,
+}
+
+impl DocumentState {
+    /// Returns the channels for a new websocket client.
+    pub(crate) fn subscribe(&self) -> Subscription {
+        Subscription {
+            preview_rx: self.tx.subscribe(),
+            scroll_rx: self.scroll_tx.subscribe(),
+            events_tx: self.events_tx.clone(),
+        }
+    }
+
+    pub(crate) fn scroll_to_line(&self, line: usize) {
+        // Sending only fails if there are no clients to scroll.
+        let _ = self.scroll_tx.send(line);
+    }
+
+    pub(crate) async fn send(&self, markdown: &str) -> Result<()> {
+        // Cancel any in-flight render before registering for cancellation ourselves.
+        self.superseded.notify_waiters();
+        let superseded = self.superseded.notified();
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let (renderer, render_timeout, debounce) = {
+            let config = self.config.read().unwrap();
+            (
+                Arc::clone(&config.renderer),
+                config.render_timeout,
+                config.debounce,
+            )
+        };
+
+        let mut output = mem::take(&mut *self.output.lock().unwrap());
+        output.clear();
+
+        // Heuristic taken from rustdoc
+        output.reserve(markdown.len() * 3 / 2);
+
+        let render = async {
+            // Markdown sent during the debounce window supersedes this call before it renders.
+            if let Some(debounce) = debounce {
+                tokio::time::sleep(debounce).await;
+            }
+
+            let deadline = render_timeout.map(|timeout| Instant::now() + timeout);
+            let render = renderer.render_document(markdown, &mut output, deadline);
+
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), render)
+                    .await
+                    .map_err(|_| Error::RendererTimeout)?,
+                None => render.await,
+            }
+        };
+
+        let result = tokio::select! {
+            res = render => res,
+            _ = superseded => {
+                debug!("render cancelled by newer markdown");
+                return Ok(());
+            }
+        };
+
+        // The generation is checked while the channel is locked, so that a newer render is never
+        // overwritten by an older one.
+        let is_current = || self.generation.load(Ordering::SeqCst) == generation;
+
+        match result {
+            Ok(metadata) => {
+                self.tx.send_if_modified(|preview| {
+                    if !is_current() {
+                        return false;
+                    }
+
+                    mem::swap(&mut preview.html, &mut output);
+                    preview.metadata = metadata;
+                    preview.revision += 1;
+                    preview.error = None;
+                    true
+                });
+                *self.output.lock().unwrap() = output;
+                Ok(())
+            }
+            Err(e) => {
+                warn!("{}", display_chain(&e));
+                self.tx.send_if_modified(|preview| {
+                    if !is_current() {
+                        return false;
+                    }
+
+                    preview.error = Some(display_chain(&e));
+                    true
+                });
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for DocumentState {
+    fn drop(&mut self) {
+        if let Some(documents) = self.documents.upgrade() {
+            documents.remove_if_unused(&self.name);
+        }
+    }
+}
+
+/// The documents served by the server, keyed by name.
+///
+/// The root document has an empty name.
+#[derive(Debug)]
+pub(crate) struct Documents {
+    config: Arc<RwLock<Config>>,
+    documents: RwLock<BTreeMap<String, Weak<DocumentState>>>,
+}
+
+impl Documents {
+    pub(crate) fn new(config: Arc<RwLock<Config>>) -> Self {
+        Documents {
+            config,
+            documents: RwLock::default(),
+        }
+    }
+
+    /// Returns the document with the given name, if it exists.
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<DocumentState>> {
+        self.documents.read().unwrap().get(name)?.upgrade()
+    }
+
+    /// Returns the document with the given name, creating it if it does not exist.
+    pub(crate) fn get_or_insert(self: &Arc<Self>, name: &str) -> Arc<DocumentState> {
+        let mut documents = self.documents.write().unwrap();
+
+        if let Some(document) = documents.get(name).and_then(Weak::upgrade) {
+            return document;
+        }
+
+        info!("opening document: {:?}", name);
+
+        let document = Arc::new(DocumentState {
+            name: name.to_owned(),
+            config: Arc::clone(&self.config),
+            documents: Arc::downgrade(self),
+            generation: AtomicU64::new(0),
+            superseded: Notify::new(),
+            output: Mutex::default(),
+            tx: watch::channel(Preview::default()).0,
+            scroll_tx: broadcast::channel(SCROLL_CAPACITY).0,
+            events_tx: broadcast::channel(EVENTS_CAPACITY).0,
+        });
+
+        documents.insert(name.to_owned(), Arc::downgrade(&document));
+
+        document
+    }
+
+    /// Returns the names of the open documents, excluding the root document.
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.documents
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(name, document)| !name.is_empty() && document.strong_count() > 0)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn remove_if_unused(&self, name: &str) {
+        let mut documents = self.documents.write().unwrap();
+
+        // A new document with the same name may have been opened in the meantime.
+        if documents
+            .get(name)
+            .is_some_and(|document| document.strong_count() == 0)
+        {
+            info!("closing document: {:?}", name);
+            documents.remove(name);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Error types.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::ExitError;
+
+/// A specialized [`Result`](std::result::Result) type for aurelius operations.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors that can occur while running the server.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The server could not bind to the requested address.
+    #[error("could not bind server")]
+    Bind(#[source] io::Error),
+
+    /// The renderer process could not be spawned.
+    #[error("could not spawn renderer")]
+    RendererSpawn(#[source] io::Error),
+
+    /// The renderer process exited unsuccessfully.
+    #[error(transparent)]
+    RendererExit(#[from] ExitError),
+
+    /// The program of a [`CommandProcessor`](crate::CommandProcessor) could not be spawned.
+    #[error("could not spawn block processor")]
+    ProcessorSpawn(#[source] io::Error),
+
+    /// The program of a [`CommandProcessor`](crate::CommandProcessor) exited unsuccessfully.
+    #[error(transparent)]
+    ProcessorExit(ExitError),
+
+    /// The renderer did not finish within the render timeout.
+    #[error("renderer timed out")]
+    RendererTimeout,
+
+    /// The renderer failed for another reason, such as an I/O error while communicating with an
+    /// external renderer.
+    ///
+    /// Custom [`Renderer`](crate::Renderer)s should use this variant to report their own errors.
+    #[error("render failed")]
+    Render(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// The syntax highlighting theme does not exist, either among the highlight.js themes served to
+    /// the browser or the themes used for server-side highlighting.
+    #[error("unknown highlight theme: {0}")]
+    Theme(String),
+
+    /// A custom CSS file could not be read.
+    #[error("could not read CSS file {}", path.display())]
+    Css {
+        /// The path of the CSS file.
+        path: PathBuf,
+
+        /// The underlying I/O error.
+        source: io::Error,
+    },
+
+    /// The browser could not be launched.
+    #[error("could not launch browser")]
+    BrowserLaunch(#[source] io::Error),
+}
+
+/// Formats an error followed by each of its sources, separated by `: `.
+///
+/// Error messages do not include their source, so this is used where errors are shown to the user.
+pub(crate) fn display_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+
+    message
+}
@@ -43,36 +43,67 @@
 #![warn(missing_debug_implementations)]
 #![warn(missing_docs)]
 
-use std::cell::RefCell;
 use std::fs;
 use std::io;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use axum::{extract::Extension, http::Uri, routing::get, Router};
-use pulldown_cmark::{Options, Parser};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use futures::Stream;
 use tokio::process::Command;
 use tokio::sync::oneshot;
-use tokio::sync::watch::{self, Sender};
+use tokio::task::JoinHandle;
+use tokio_util::sync::{CancellationToken, DropGuard};
 use tower_http::trace::TraceLayer;
 use tracing::log::*;
 
+mod builder;
+mod diff;
+mod document;
+mod error;
+mod math;
+mod protocol;
+mod render;
 mod service;
 
+use crate::document::Documents;
+use crate::service::Clients;
+
+pub use crate::builder::ServerBuilder;
+pub use crate::document::Document;
+pub use crate::error::{Error, Result};
+pub use crate::math::MathDelimiter;
+pub use crate::protocol::ClientEvent;
+#[cfg(feature = "syntax-highlighting")]
+pub use crate::render::{highlight_css, HighlightStyle};
+pub use crate::render::{
+    BlockProcessor, CommandProcessor, ExitError, ExternalRenderer, Framing, MarkdownRenderer,
+    Metadata, Options as MarkdownOptions, PersistentRenderer, Renderer,
+};
+
 /// Markdown preview server.
 ///
 /// Listens for HTTP connections and serves a page containing a live markdown preview. The page
 /// contains JavaScript to open a websocket connection back to the server for rendering updates.
+///
+/// The server always serves a root document at `/`, which is updated by [`send`][Self::send].
+/// Additional documents may be served from the same server with [`document`][Self::document].
+///
+/// The server is `Send` and `Sync`, so it may be shared between tasks (for example, in an [`Arc`])
+/// and [`send`][Self::send] may be called from any of them.
 #[derive(Debug)]
 pub struct Server {
     addr: SocketAddr,
     config: Arc<RwLock<Config>>,
-    external_renderer: Option<RefCell<Command>>,
-    output: RefCell<String>,
-    tx: Sender<String>,
+    documents: Arc<Documents>,
+    root: Document,
+    clients: Arc<Clients>,
+    server_task: JoinHandle<()>,
+    shutdown_timeout: Duration,
+    _close_guard: DropGuard,
     _shutdown_tx: oneshot::Sender<()>,
 }
 
@@ -83,21 +114,48 @@ impl Server {
     /// to determine what port was assigned.
     ///
     /// The server must be bound using a Tokio runtime.
-    pub async fn bind(addr: &SocketAddr) -> io::Result<Self> {
-        let (tx, rx) = watch::channel(String::new());
+    ///
+    /// To configure the server before binding, use [`Server::builder`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Bind`] if the server could not bind to the address.
+    pub async fn bind(addr: &SocketAddr) -> Result<Self> {
+        ServerBuilder::new().bind(addr).await
+    }
+
+    /// Returns a builder for configuring the server before binding.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    async fn start(addr: &SocketAddr, config: Config, shutdown_timeout: Duration) -> Result<Self> {
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
-        let config = Arc::default();
+        let config = Arc::new(RwLock::new(config));
+        let documents = Arc::new(Documents::new(Arc::clone(&config)));
+        let root = Document::new(documents.get_or_insert(""));
+        // Clients are given the shutdown timeout to complete the closing handshake, so that
+        // clients that do not are still connected when shutdown times out.
+        let clients = Arc::new(Clients::new(shutdown_timeout));
+        let closed = CancellationToken::new();
 
         let app = Router::new()
-            .route("/", get(service::websocket_handler))
+            .route("/", get(service::document_handler))
             .route("/__/*path", get(service::serve_asset))
-            .fallback(get(service::serve_static_file))
+            .fallback(get(service::document_handler))
             .layer(Extension(Arc::clone(&config)))
-            .layer(Extension(rx))
+            .layer(Extension(Arc::clone(&documents)))
+            .layer(Extension(Arc::clone(&clients)))
+            .layer(Extension(closed.clone()))
             .layer(TraceLayer::new_for_http());
 
-        let http_server = axum::Server::bind(addr).serve(app.into_make_service());
+        let listener = std::net::TcpListener::bind(addr).map_err(Error::Bind)?;
+        listener.set_nonblocking(true).map_err(Error::Bind)?;
+
+        let http_server = axum::Server::from_tcp(listener)
+            .map_err(|e| Error::Bind(io::Error::other(e)))?
+            .serve(app.into_make_service());
 
         let addr = http_server.local_addr();
         info!("listening on {:?}", addr);
@@ -106,18 +164,72 @@ impl Server {
             let _ = shutdown_rx.await;
         });
 
-        tokio::spawn(http_server);
+        let server_task = tokio::spawn(async move {
+            if let Err(e) = http_server.await {
+                error!("server error: {}", e);
+            }
+        });
 
         Ok(Server {
             addr,
             config,
-            external_renderer: None,
-            tx,
-            output: RefCell::new(String::new()),
+            documents,
+            root,
+            clients,
+            server_task,
+            shutdown_timeout,
+            _close_guard: closed.drop_guard(),
             _shutdown_tx: shutdown_tx,
         })
     }
 
+    /// Shuts down the server, waiting for connected clients to close.
+    ///
+    /// Connected websocket clients are sent close frames, and the HTTP server stops accepting new
+    /// connections. This method waits until all clients have disconnected and the HTTP server has
+    /// finished, up to the [shutdown timeout][ServerBuilder::shutdown_timeout].
+    ///
+    /// Dropping the server also shuts it down, but does not wait for clients to close.
+    pub async fn shutdown(self) -> ShutdownReport {
+        let Server {
+            clients,
+            server_task,
+            shutdown_timeout,
+            _close_guard: close_guard,
+            _shutdown_tx: shutdown_tx,
+            ..
+        } = self;
+
+        info!("shutting down with {} connected clients", clients.count());
+
+        // Signal websocket handlers to send close frames.
+        drop(close_guard);
+        drop(shutdown_tx);
+
+        let graceful = async {
+            let _ = server_task.await;
+            clients.wait_for_disconnect().await;
+        };
+
+        let completed = tokio::time::timeout(shutdown_timeout, graceful)
+            .await
+            .is_ok();
+
+        let connected = clients.count();
+
+        if !completed {
+            warn!(
+                "shutdown timed out with {} clients still connected",
+                connected
+            );
+        }
+
+        ShutdownReport {
+            clients: connected,
+            completed,
+        }
+    }
+
     /// Returns the socket address that the server is listening on.
     pub fn addr(&self) -> SocketAddr {
         self.addr
@@ -125,40 +237,100 @@ impl Server {
 
     /// Publish new markdown to be rendered by the server.
     ///
-    /// The new HTML will be sent to all connected websocket clients.
+    /// The new HTML will be sent to all connected websocket clients. Clients that have already
+    /// received a render are only sent the top-level elements that changed.
+    ///
+    /// If rendering fails, the error is sent to connected clients, which continue to display the
+    /// last successful render.
+    ///
+    /// If markdown is sent while a previous call is still rendering, the previous render is
+    /// cancelled and its call returns `Ok(())` without publishing anything. If a
+    /// [debounce window][Self::set_debounce] is set, rendering starts only once no more markdown
+    /// has been sent for the duration of the window.
     ///
     /// # Errors
     ///
-    /// This method forwards errors from an external renderer, if set. Otherwise, the method is
-    /// infallible.
-    pub async fn send(&self, markdown: &str) -> io::Result<()> {
-        let mut output = self.output.take();
-        output.clear();
-
-        // Heuristic taken from rustdoc
-        output.reserve(markdown.len() * 3 / 2);
-
-        if let Some(renderer) = &self.external_renderer {
-            let child = renderer.borrow_mut().spawn()?;
-
-            child.stdin.unwrap().write_all(markdown.as_bytes()).await?;
-
-            child.stdout.unwrap().read_to_string(&mut output).await?;
-        } else {
-            let parser = Parser::new_ext(
-                markdown,
-                Options::ENABLE_FOOTNOTES
-                    | Options::ENABLE_TABLES
-                    | Options::ENABLE_STRIKETHROUGH
-                    | Options::ENABLE_TASKLISTS,
-            );
+    /// This method forwards errors from the renderer. The default renderer is infallible.
+    ///
+    /// If the render does not complete within the [render timeout][Self::set_render_timeout],
+    /// [`Error::RendererTimeout`] is returned.
+    pub async fn send(&self, markdown: &str) -> Result<()> {
+        self.root.send(markdown).await
+    }
 
-            pulldown_cmark::html::push_html(&mut output, parser);
-        };
+    /// Scroll connected clients to the element rendered from a line of the markdown.
+    ///
+    /// Lines are numbered from 1. Clients scroll to the last block element that begins on or
+    /// before `line`, so this can be called with the cursor position of an editor to keep the
+    /// preview in sync.
+    ///
+    /// This relies on the `data-source-line` attributes emitted by [`MarkdownRenderer`]. Custom
+    /// renderers may emit the same attributes to support scrolling.
+    pub fn scroll_to_line(&self, line: usize) {
+        self.root.scroll_to_line(line);
+    }
 
-        self.output.replace(self.tx.send_replace(output));
+    /// Returns a stream of the events reported by clients of the root document.
+    ///
+    /// Clicking a rendered block element in the browser reports a [`ClientEvent::Click`] with the
+    /// line of the markdown on which the block begins, so that an editor can jump to it. Like
+    /// scrolling, this relies on the `data-source-line` attributes emitted by
+    /// [`MarkdownRenderer`].
+    ///
+    /// Each stream receives the events reported after it was created. Events are dropped if the
+    /// stream falls too far behind. The stream ends when the server is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn dox() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::net::SocketAddr;
+    /// use aurelius::{ClientEvent, Server};
+    /// use futures::StreamExt;
+    ///
+    /// let addr = "127.0.0.1:1337".parse::<SocketAddr>()?;
+    /// let server = Server::bind(&addr).await?;
+    ///
+    /// let mut events = Box::pin(server.events());
+    /// while let Some(event) = events.next().await {
+    ///     if let ClientEvent::Click { line } = event {
+    ///         println!("jump to line {}", line);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&self) -> impl Stream<Item = ClientEvent> + Send + 'static {
+        self.root.events()
+    }
 
-        Ok(())
+    /// Returns a handle to the document with the given name, opening it if necessary.
+    ///
+    /// The document is served at `/<name>` with its own websocket channel, independently of the
+    /// root document. The root page lists the open documents. Since the name is used as the URL
+    /// path, it should not require percent-encoding. An empty name refers to the root document.
+    ///
+    /// Calling this method again with the same name returns a handle to the same document. The
+    /// document is closed when all of its handles are dropped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn dox() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::net::SocketAddr;
+    /// use aurelius::Server;
+    ///
+    /// let addr = "127.0.0.1:1337".parse::<SocketAddr>()?;
+    /// let server = Server::bind(&addr).await?;
+    ///
+    /// // Served at http://127.0.0.1:1337/notes.md
+    /// let notes = server.document("notes.md");
+    /// notes.send("# Notes").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn document(&self, name: &str) -> Document {
+        Document::new(self.documents.get_or_insert(name.trim_start_matches('/')))
     }
 
     /// Set the directory that static files will be served from.
@@ -183,33 +355,114 @@ impl Server {
     ///
     /// Accepts URLs and absolute paths. URLs will be inserted as `<link>` tags. The contents of
     /// the paths will be read from disk and served in `<style>` tags.
-    pub fn set_custom_css(&mut self, stylesheets: Vec<String>) -> io::Result<()> {
-        let mut files = vec![];
-        let mut links = vec![];
-
-        for stylesheet in &stylesheets {
-            // NB: Absolute paths on Windows will parse as URLs.
-            match stylesheet.parse::<Uri>() {
-                Ok(url)
-                    if url.scheme_str() == Some("http") || url.scheme_str() == Some("https") =>
-                {
-                    links.push(url)
-                }
-                _ => files.push(Path::new(stylesheet.trim_start_matches("file://"))),
-            }
-        }
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Css`] if a file could not be read.
+    pub fn set_custom_css(&mut self, stylesheets: Vec<String>) -> Result<()> {
+        let (links, styles) = load_custom_css(&stylesheets)?;
 
         let mut config = self.config.write().unwrap();
-
-        config.custom_styles = files
-            .into_iter()
-            .map(fs::read_to_string)
-            .collect::<Result<Vec<_>, _>>()?;
         config.css_links = links;
+        config.custom_styles = styles;
 
         Ok(())
     }
 
+    /// Set whether math in the rendered HTML is rendered by KaTeX.
+    ///
+    /// KaTeX is bundled with the server, so math is rendered without network access. Math is
+    /// rendered by default.
+    pub fn set_math(&mut self, enabled: bool) {
+        self.config.write().unwrap().math = enabled;
+    }
+
+    /// Set the delimiters that surround math in the rendered HTML.
+    ///
+    /// Defaults to [`MathDelimiter::defaults`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn dox() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::net::SocketAddr;
+    /// use aurelius::{MathDelimiter, Server};
+    ///
+    /// let addr = "127.0.0.1:1337".parse::<SocketAddr>()?;
+    /// let mut server = Server::bind(&addr).await?;
+    ///
+    /// server.set_math_delimiters(vec![
+    ///     MathDelimiter::display("$$", "$$"),
+    ///     MathDelimiter::inline("$`", "`$"),
+    /// ]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_math_delimiters(&mut self, delimiters: Vec<MathDelimiter>) {
+        self.config.write().unwrap().math_delimiters = delimiters;
+    }
+
+    /// Set whether fenced code blocks with the `mermaid` language are rendered as diagrams.
+    ///
+    /// Diagrams are rendered in the browser by mermaid.js, which is bundled with the server. A
+    /// diagram with a syntax error is replaced by the error and its source. Diagrams are rendered
+    /// by default.
+    pub fn set_mermaid(&mut self, enabled: bool) {
+        self.config.write().unwrap().mermaid = enabled;
+    }
+
+    /// Set whether a table of contents is shown beside the preview.
+    ///
+    /// On wide screens, the table of contents is a sidebar to the left of the preview. On narrow
+    /// screens, it is collapsed above the preview instead. The table of contents links to the
+    /// headings of the document that have an `id`, and is rebuilt in the browser whenever the
+    /// preview changes. The default [`MarkdownRenderer`] gives headings ids unless
+    /// [`MarkdownRenderer::heading_ids`] is disabled. Disabled by default.
+    pub fn set_toc(&mut self, enabled: bool) {
+        self.config.write().unwrap().toc = enabled;
+    }
+
+    /// Set the renderer used to convert markdown to HTML.
+    ///
+    /// Defaults to [`MarkdownRenderer`].
+    pub fn set_renderer(&mut self, renderer: impl Renderer + 'static) {
+        self.config.write().unwrap().renderer = Arc::new(renderer);
+    }
+
+    /// Set the maximum amount of time that a render may take.
+    ///
+    /// If the renderer does not finish within the timeout, the render is cancelled and
+    /// [`send`][Self::send] returns an error. Any external renderer process is killed.
+    ///
+    /// By default, there is no timeout.
+    pub fn set_render_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.write().unwrap().render_timeout = timeout;
+    }
+
+    /// Set the amount of time to wait for more markdown before rendering.
+    ///
+    /// Each call to [`send`][Self::send] waits for the debounce window to elapse before rendering.
+    /// If more markdown is sent during the window, the earlier call returns `Ok(())` without
+    /// rendering, so a burst of calls is coalesced into a single render of the last markdown. The
+    /// last markdown sent is always rendered.
+    ///
+    /// By default, there is no debounce window and markdown is rendered immediately.
+    pub fn set_debounce(&mut self, debounce: Option<Duration>) {
+        self.config.write().unwrap().debounce = debounce;
+    }
+
+    /// Set whether clients that do not negotiate a protocol version are sent raw HTML.
+    ///
+    /// Clients negotiate a version of the JSON message protocol by requesting the `aurelius.v1`
+    /// websocket subprotocol. By default, clients that request no subprotocol are sent JSON
+    /// messages as well. Enable this option to support older clients that expect each message to
+    /// be the rendered HTML. These clients are not sent render errors or scroll commands.
+    ///
+    /// Only affects clients that connect after this option is set.
+    pub fn set_raw_html_fallback(&mut self, enabled: bool) {
+        self.config.write().unwrap().raw_html_fallback = enabled;
+    }
+
     /// Set an external program to use for rendering the markdown.
     ///
     /// By default, aurelius uses [`pulldown_cmark`] to render markdown in-process.
@@ -217,7 +470,8 @@ impl Server {
     /// for most use-cases. However, other markdown renderers may provide additional features.
     ///
     /// The `Command` supplied to this function should expect markdown on stdin and print HTML on
-    /// stdout.
+    /// stdout. This is a shorthand for setting an [`ExternalRenderer`] with
+    /// [`set_renderer`][Self::set_renderer].
     ///
     /// # Example
     ///
@@ -244,12 +498,8 @@ impl Server {
     /// [`pulldown_cmark`]: https://github.com/raphlinus/pulldown-cmark
     /// [CommonMark]: https://commonmark.org/
     /// [`pandoc`]: https://pandoc.org/
-    pub fn set_external_renderer(&mut self, mut command: Command) {
-        command
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null());
-        self.external_renderer = Some(RefCell::new(command));
+    pub fn set_external_renderer(&mut self, command: Command) {
+        self.set_renderer(ExternalRenderer::new(command));
     }
 
     /// Opens the user's default browser with the server's URL in the background.
@@ -262,7 +512,11 @@ impl Server {
     /// | Linux    | `xdg-open` |
     /// | OS X     | `open -g`  |
     /// | Windows  | `explorer` |
-    pub fn open_browser(&self) -> io::Result<()> {
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::BrowserLaunch`] if the browser could not be spawned.
+    pub fn open_browser(&self) -> Result<()> {
         let command = if cfg!(target_os = "macos") {
             let mut command = Command::new("open");
             command.arg("-g");
@@ -278,32 +532,88 @@ impl Server {
 
     /// Opens a browser with a specified command. The HTTP address of the server will be appended
     /// to the command as an argument.
-    pub fn open_specific_browser(&self, mut command: Command) -> io::Result<()> {
-        command.arg(&format!("http://{}", self.addr()));
+    pub fn open_specific_browser(&self, mut command: Command) -> Result<()> {
+        command.arg(format!("http://{}", self.addr()));
 
         command.stdout(Stdio::null()).stderr(Stdio::null());
 
         info!("spawning browser: {:?}", command);
-        command.spawn()?;
+        command.spawn().map_err(Error::BrowserLaunch)?;
         Ok(())
     }
 }
 
+/// The outcome of [`Server::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ShutdownReport {
+    /// The number of websocket clients that were still connected when shutdown finished or timed
+    /// out. This is zero if every client closed cleanly.
+    pub clients: usize,
+
+    /// Whether all clients disconnected and the HTTP server finished within the shutdown timeout.
+    pub completed: bool,
+}
+
+/// Splits stylesheets into remote links and the contents of local files.
+fn load_custom_css(stylesheets: &[String]) -> Result<(Vec<Uri>, Vec<String>)> {
+    let mut files = vec![];
+    let mut links = vec![];
+
+    for stylesheet in stylesheets {
+        // NB: Absolute paths on Windows will parse as URLs.
+        match stylesheet.parse::<Uri>() {
+            Ok(url) if url.scheme_str() == Some("http") || url.scheme_str() == Some("https") => {
+                links.push(url)
+            }
+            _ => files.push(Path::new(stylesheet.trim_start_matches("file://"))),
+        }
+    }
+
+    let styles = files
+        .into_iter()
+        .map(|path| {
+            fs::read_to_string(path).map_err(|source| Error::Css {
+                path: path.to_owned(),
+                source,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((links, styles))
+}
+
 #[derive(Debug)]
 pub(crate) struct Config {
+    renderer: Arc<dyn Renderer>,
+    render_timeout: Option<Duration>,
+    debounce: Option<Duration>,
     static_root: Option<PathBuf>,
     highlight_theme: String,
     css_links: Vec<Uri>,
     custom_styles: Vec<String>,
+    math: bool,
+    math_delimiters: Vec<MathDelimiter>,
+    mermaid: bool,
+    toc: bool,
+    raw_html_fallback: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            renderer: Arc::new(MarkdownRenderer::new()),
+            render_timeout: None,
+            debounce: None,
             static_root: None,
             highlight_theme: String::from("github"),
             css_links: vec![],
             custom_styles: vec![],
+            math: true,
+            math_delimiters: MathDelimiter::defaults(),
+            mermaid: true,
+            toc: false,
+            raw_html_fallback: false,
         }
     }
 }
@@ -327,6 +637,23 @@ mod tests {
         Ok(Server::bind(&addr).await?)
     }
 
+    /// Extracts the HTML from an HTML message.
+    fn html(message: &Message) -> String {
+        let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(message["type"], "html");
+        message["html"].as_str().unwrap().to_owned()
+    }
+
+    /// Receives the next message and parses it as JSON.
+    async fn next_json<S: AsyncRead + AsyncWrite + Unpin>(
+        websocket: &mut WebSocketStream<S>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let message = timeout(Duration::from_secs(5), websocket.try_next())
+            .await??
+            .unwrap();
+        Ok(serde_json::from_str(message.to_text()?)?)
+    }
+
     async fn assert_websocket_closed<S: AsyncRead + AsyncWrite + Unpin>(
         websocket: &mut WebSocketStream<S>,
     ) {
@@ -384,11 +711,21 @@ mod tests {
 
         server.send("<p>Hello, world!</p>").await.unwrap();
         let message = websocket.next().await.unwrap().unwrap();
-        assert_eq!(message.to_text().unwrap(), "<p>Hello, world!</p>");
+        assert_eq!(html(&message), "<p>Hello, world!</p>");
 
         server.send("<p>Goodbye, world!</p>").await.unwrap();
         let message = websocket.next().await.unwrap().unwrap();
-        assert_eq!(message.to_text().unwrap(), "<p>Goodbye, world!</p>");
+        let message: serde_json::Value = serde_json::from_str(message.to_text()?)?;
+        assert_eq!(
+            message,
+            serde_json::json!({
+                "type": "patch",
+                "start": 0,
+                "delete": 1,
+                "insert": ["<p>Goodbye, world!</p>"],
+                "blocks": 1,
+            })
+        );
 
         Ok(())
     }
@@ -402,7 +739,167 @@ mod tests {
 
         server.send("*Hello*").await?;
         let message = websocket.next().await.unwrap()?;
-        assert_eq!(message.to_text()?.trim(), "<p><em>Hello</em></p>");
+        assert_eq!(
+            html(&message).trim(),
+            r#"<p data-source-line="1"><em>Hello</em></p>"#
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancel_superseded_render() -> anyhow::Result<()> {
+        use async_trait::async_trait;
+
+        use crate::Renderer;
+
+        /// Renders markdown as-is, but takes a long time to render "slow".
+        #[derive(Debug)]
+        struct SlowRenderer;
+
+        #[async_trait]
+        impl Renderer for SlowRenderer {
+            async fn render(&self, markdown: &str, html: &mut String) -> crate::Result<()> {
+                if markdown == "slow" {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                html.push_str(markdown);
+                Ok(())
+            }
+        }
+
+        let mut server = new_server().await?;
+        server.set_renderer(SlowRenderer);
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        let (slow, fast) = timeout(Duration::from_secs(5), async {
+            tokio::join!(server.send("slow"), async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                server.send("fast").await
+            })
+        })
+        .await?;
+        slow?;
+        fast?;
+
+        let message = websocket.next().await.unwrap()?;
+        assert_eq!(html(&message), "fast");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_render_error() -> anyhow::Result<()> {
+        use std::io;
+
+        use async_trait::async_trait;
+
+        use crate::Renderer;
+
+        /// Renders markdown as-is, unless it is "error".
+        #[derive(Debug)]
+        struct FailingRenderer;
+
+        #[async_trait]
+        impl Renderer for FailingRenderer {
+            async fn render(&self, markdown: &str, html: &mut String) -> crate::Result<()> {
+                if markdown == "error" {
+                    return Err(crate::Error::Render(Box::new(io::Error::other(
+                        "something went wrong",
+                    ))));
+                }
+                html.push_str(markdown);
+                Ok(())
+            }
+        }
+
+        let mut server = new_server().await?;
+        server.set_renderer(FailingRenderer);
+
+        server.send("good").await?;
+        assert!(server.send("error").await.is_err());
+
+        // Clients that connect after the error receive the last good render, then the error.
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        let message = websocket.next().await.unwrap()?;
+        assert_eq!(html(&message), "good");
+
+        let message = websocket.next().await.unwrap()?;
+        let message: serde_json::Value = serde_json::from_str(message.to_text()?)?;
+        assert_eq!(message["type"], "error");
+        assert_eq!(message["message"], "render failed: something went wrong");
+
+        server.send("better").await?;
+        let message = websocket.next().await.unwrap()?;
+        assert_eq!(html(&message), "better");
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({ "type": "error_cleared" })
+        );
+
+        // Undoing the edit that caused an error clears it, even though no blocks changed.
+        server.send("<p>best</p>").await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "html");
+
+        assert!(server.send("error").await.is_err());
+        assert_eq!(next_json(&mut websocket).await?["type"], "error");
+
+        server.send("<p>best</p>").await?;
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({ "type": "error_cleared" })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn server_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Server>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn send_from_spawned_tasks() -> anyhow::Result<()> {
+        use std::sync::Arc;
+
+        let server = Arc::new(new_server().await?);
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        let tasks = (0..10)
+            .map(|i| {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move { server.send(&format!("Task {}", i)).await })
+            })
+            .collect::<Vec<_>>();
+
+        for task in tasks {
+            task.await??;
+        }
+
+        server.send("Done").await?;
+
+        timeout(Duration::from_secs(5), async {
+            loop {
+                // Depending on the renders that the client received, this is either the full HTML
+                // or a patch.
+                let message = websocket.next().await.unwrap()?;
+                if message
+                    .to_text()?
+                    .contains(r#"<p data-source-line=\"1\">Done</p>"#)
+                {
+                    return anyhow::Ok(());
+                }
+            }
+        })
+        .await??;
 
         Ok(())
     }
@@ -423,6 +920,71 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn shutdown() -> anyhow::Result<()> {
+        let server = new_server().await?;
+        let addr = server.addr();
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", addr)).await?;
+
+        // Wait for the connection to be registered.
+        server.send("Hello").await?;
+        websocket.next().await.unwrap()?;
+
+        let client = tokio::spawn(async move {
+            assert_matches!(websocket.next().await, Some(Ok(Message::Close(None))));
+            // Complete the closing handshake.
+            while websocket.next().await.is_some() {}
+        });
+
+        let report = server.shutdown().await;
+        assert_eq!(report.clients, 0);
+        assert!(report.completed);
+
+        client.await?;
+
+        assert!(reqwest::get(&format!("http://{}", addr)).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_unresponsive_client() -> anyhow::Result<()> {
+        let addr = lookup_host("localhost:0").await?.next().unwrap();
+        let server = Server::builder()
+            .shutdown_timeout(Duration::from_millis(200))
+            .bind(&addr)
+            .await?;
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        // Wait for the connection to be registered.
+        server.send("Hello").await?;
+        websocket.next().await.unwrap()?;
+
+        // The client never reads the close frame, so it does not complete the closing handshake.
+        let report = server.shutdown().await;
+        assert_eq!(report.clients, 1);
+        assert!(!report.completed);
+
+        drop(websocket);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_without_clients() -> anyhow::Result<()> {
+        let server = new_server().await?;
+
+        let report = server.shutdown().await;
+        assert_eq!(report.clients, 0);
+        assert!(report.completed);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn queue_html_if_no_clients() -> Result<(), Box<dyn Error>> {
         let server = new_server().await?;
@@ -436,7 +998,10 @@ mod tests {
             .await??
             .unwrap();
         assert!(message.is_text(), "message was not text: {:?}", message);
-        assert_eq!(message.to_text().unwrap().trim(), "<h1>Markdown</h1>");
+        assert_eq!(
+            html(&message).trim(),
+            r##"<h1 id="markdown" data-source-line="1"><a class="anchor" href="#markdown" aria-hidden="true"></a>Markdown</h1>"##
+        );
 
         Ok(())
     }
@@ -456,4 +1021,285 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn send_to_document() -> anyhow::Result<()> {
+        let server = new_server().await?;
+        let notes = server.document("notes.md");
+
+        let (mut root, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}/notes.md", server.addr()))
+                .await?;
+
+        notes.send("# Notes").await?;
+        server.send("# Root").await?;
+
+        let message = timeout(Duration::from_secs(5), websocket.try_next())
+            .await??
+            .unwrap();
+        assert_eq!(
+            html(&message).trim(),
+            r##"<h1 id="notes" data-source-line="1"><a class="anchor" href="#notes" aria-hidden="true"></a>Notes</h1>"##
+        );
+
+        let message = timeout(Duration::from_secs(5), root.try_next())
+            .await??
+            .unwrap();
+        assert_eq!(
+            html(&message).trim(),
+            r##"<h1 id="root" data-source-line="1"><a class="anchor" href="#root" aria-hidden="true"></a>Root</h1>"##
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_documents() -> anyhow::Result<()> {
+        let server = new_server().await?;
+        let _notes = server.document("notes.md");
+        let _todo = server.document("/todo.md");
+
+        let body = reqwest::get(&format!("http://{}", server.addr()))
+            .await?
+            .text()
+            .await?;
+        assert!(body.contains(r#"href="/notes.md""#));
+        assert!(body.contains(r#"href="/todo.md""#));
+
+        let res = reqwest::get(&format!("http://{}/todo.md", server.addr())).await?;
+        assert!(res.text().await?.contains("<html>"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn close_document_on_drop() -> anyhow::Result<()> {
+        let server = new_server().await?;
+        let notes = server.document("notes.md");
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}/notes.md", server.addr()))
+                .await?;
+
+        drop(notes);
+
+        assert_matches!(websocket.next().await, Some(Ok(Message::Close(None))));
+
+        let res = reqwest::get(&format!("http://{}/notes.md", server.addr())).await?;
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scroll_to_line() -> anyhow::Result<()> {
+        let server = new_server().await?;
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        // Wait for the connection to be registered.
+        server.send("# Heading\n\nParagraph").await?;
+        websocket.next().await.unwrap()?;
+
+        server.scroll_to_line(3);
+
+        let message = timeout(Duration::from_secs(5), websocket.try_next())
+            .await??
+            .unwrap();
+        let message: serde_json::Value = serde_json::from_str(message.to_text()?)?;
+        assert_eq!(message["type"], "scroll");
+        assert_eq!(message["line"], 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_events() -> anyhow::Result<()> {
+        use crate::ClientEvent;
+
+        let server = new_server().await?;
+        let mut events = Box::pin(server.events());
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        websocket
+            .send(Message::Text(String::from("not an event")))
+            .await?;
+        websocket
+            .send(Message::Text(String::from(r#"{"type":"click","line":7}"#)))
+            .await?;
+        websocket
+            .send(Message::Text(String::from(
+                r#"{"type":"task_toggle","offset":2,"checked":true}"#,
+            )))
+            .await?;
+
+        let event = timeout(Duration::from_secs(5), events.next()).await?;
+        assert_eq!(event, Some(ClientEvent::Click { line: 7 }));
+
+        let event = timeout(Duration::from_secs(5), events.next()).await?;
+        assert_eq!(
+            event,
+            Some(ClientEvent::TaskToggle {
+                offset: 2,
+                checked: true
+            })
+        );
+
+        drop(server);
+
+        assert_eq!(events.next().await, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn negotiate_protocol() -> anyhow::Result<()> {
+        use async_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let server = new_server().await?;
+
+        let mut request = format!("ws://{}", server.addr()).into_client_request()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            "aurelius.v2, aurelius.v1".parse()?,
+        );
+        let (mut websocket, response) = async_tungstenite::tokio::connect_async(request).await?;
+        assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "aurelius.v1");
+
+        server.send("<p>Hello, world!</p>").await?;
+        let message = websocket.next().await.unwrap()?;
+        assert_eq!(html(&message), "<p>Hello, world!</p>");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn patch_changed_blocks() -> anyhow::Result<()> {
+        let server = new_server().await?;
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        server
+            .send("<h1>Title</h1>\n\n<p>One</p>\n\n<p>Two</p>")
+            .await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "html");
+
+        server
+            .send("<h1>Title</h1>\n\n<p>Changed</p>\n\n<p>Two</p>")
+            .await?;
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({
+                "type": "patch",
+                "start": 1,
+                "delete": 1,
+                "insert": ["<p>Changed</p>"],
+                "blocks": 3,
+            })
+        );
+
+        // Top-level text cannot be patched, so the full HTML is sent.
+        server.send("<div>Block</div>\nText").await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "html");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resync() -> anyhow::Result<()> {
+        let server = new_server().await?;
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        // The browser parses this block into three elements, so patches would not apply.
+        server.send("<p><div>One</div></p>").await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "html");
+
+        websocket
+            .send(Message::Text(r#"{"type":"resync"}"#.into()))
+            .await?;
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({ "type": "html", "html": "<p><div>One</div></p>" })
+        );
+
+        // Patches are relative to the HTML that was sent again.
+        server.send("<p><div>Two</div></p>").await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "patch");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn front_matter_metadata() -> anyhow::Result<()> {
+        let server = new_server().await?;
+
+        let (mut websocket, _) =
+            async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+        server
+            .send("---\ntitle: Design <draft>\n---\n\nText")
+            .await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "html");
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({
+                "type": "metadata",
+                "metadata": { "title": "Design <draft>" },
+            })
+        );
+
+        let body = reqwest::get(&format!("http://{}", server.addr()))
+            .await?
+            .text()
+            .await?;
+        assert!(body.contains("<title>Design &lt;draft&gt;</title>"));
+
+        server.send("Text").await?;
+        assert_eq!(next_json(&mut websocket).await?["type"], "patch");
+        assert_eq!(
+            next_json(&mut websocket).await?,
+            serde_json::json!({ "type": "metadata", "metadata": null })
+        );
+
+        let body = reqwest::get(&format!("http://{}", server.addr()))
+            .await?
+            .text()
+            .await?;
+        assert!(body.contains("<title>Markdown Composer</title>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_blocks() {
+        use crate::diff::split_blocks;
+
+        assert_eq!(
+            split_blocks(
+                "<h1 id=\"a>b\">Title</h1>\n<hr />\n<!-- comment -->\n<ul>\n<li>a</li>\n</ul>\n"
+            ),
+            Some(vec![
+                "<h1 id=\"a>b\">Title</h1>",
+                "<hr />",
+                "<ul>\n<li>a</li>\n</ul>"
+            ])
+        );
+        assert_eq!(
+            split_blocks("<p>1 < 2</p><script>if (a</b) {}</script>"),
+            Some(vec!["<p>1 < 2</p>", "<script>if (a</b) {}</script>"])
+        );
+        assert_eq!(split_blocks(""), Some(vec![]));
+
+        assert_eq!(split_blocks("text <p>a</p>"), None);
+        assert_eq!(split_blocks("<p>a"), None);
+        assert_eq!(split_blocks("<p>a</div>"), None);
+    }
 }
@@ -0,0 +1,44 @@
+//! Configuration for rendering math in the preview.
+
+use serde::Serialize;
+
+/// A pair of delimiters that surround math in the rendered HTML.
+///
+/// Math is rendered in the browser by KaTeX, which is bundled with the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MathDelimiter {
+    left: String,
+    right: String,
+    display: bool,
+}
+
+impl MathDelimiter {
+    /// Delimiters for math that is rendered inline with the surrounding text, such as `$...$`.
+    pub fn inline(left: impl Into<String>, right: impl Into<String>) -> Self {
+        MathDelimiter {
+            left: left.into(),
+            right: right.into(),
+            display: false,
+        }
+    }
+
+    /// Delimiters for math that is rendered as a centered block, such as `$$...$$`.
+    pub fn display(left: impl Into<String>, right: impl Into<String>) -> Self {
+        MathDelimiter {
+            left: left.into(),
+            right: right.into(),
+            display: true,
+        }
+    }
+
+    /// The delimiters used by default: `$$...$$` and `\[...\]` for display math, and `$...$` and
+    /// `\(...\)` for inline math.
+    pub fn defaults() -> Vec<MathDelimiter> {
+        vec![
+            MathDelimiter::display("$$", "$$"),
+            MathDelimiter::display(r"\[", r"\]"),
+            MathDelimiter::inline("$", "$"),
+            MathDelimiter::inline(r"\(", r"\)"),
+        ]
+    }
+}
@@ -0,0 +1,132 @@
+//! Messages exchanged with clients over the websocket connection.
+//!
+//! Clients negotiate the version of the protocol by requesting a websocket subprotocol, such as
+//! `aurelius.v1`. Each message is a JSON object with a `type` field identifying the kind of
+//! message.
+//!
+//! Clients that do not request a subprotocol are sent version 1 messages, unless the server is
+//! configured to send them raw HTML instead.
+
+use axum::http::HeaderValue;
+use serde::{Deserialize, Serialize};
+
+use crate::Metadata;
+
+/// The websocket subprotocols supported by the server, in order of preference.
+pub(crate) const SUBPROTOCOLS: [&str; 1] = ["aurelius.v1"];
+
+/// The protocol used to communicate with a websocket client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// Each message is the full rendered HTML of the document. Other messages are not sent.
+    RawHtml,
+
+    /// Version 1 of the JSON message protocol.
+    V1,
+}
+
+impl Protocol {
+    /// Determines the protocol from the subprotocol selected during the websocket handshake.
+    pub(crate) fn negotiate(subprotocol: Option<&HeaderValue>, raw_html_fallback: bool) -> Self {
+        match subprotocol {
+            Some(subprotocol) if subprotocol == SUBPROTOCOLS[0] => Protocol::V1,
+            _ if raw_html_fallback => Protocol::RawHtml,
+            _ => Protocol::V1,
+        }
+    }
+
+    /// Encodes a message for the client, or returns `None` if the protocol does not support it.
+    pub(crate) fn encode(self, message: &ServerMessage<'_>) -> Option<String> {
+        match (self, message) {
+            (Protocol::V1, message) => Some(message.to_json()),
+            (Protocol::RawHtml, ServerMessage::Html { html }) => Some((*html).to_owned()),
+            (Protocol::RawHtml, _) => None,
+        }
+    }
+}
+
+/// A message sent from the server to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ServerMessage<'a> {
+    /// The full rendered HTML of the document.
+    Html { html: &'a str },
+
+    /// Replace `delete` top-level elements of the document, starting at index `start`, with the
+    /// elements in `insert`.
+    ///
+    /// `blocks` is the number of top-level elements after the patch is applied. If the client's
+    /// document has a different number of elements, such as when the browser parsed raw HTML into
+    /// different elements, it should send a [`ControlMessage::Resync`] instead of applying patches.
+    Patch {
+        start: usize,
+        delete: usize,
+        insert: &'a [&'a str],
+        blocks: usize,
+    },
+
+    /// Rendering failed. The client should continue to display the last successful render.
+    Error { message: &'a str },
+
+    /// A render succeeded after an error was sent. The client should stop displaying the error.
+    ///
+    /// This is sent even if the render did not change the HTML, in which case no other message
+    /// is sent.
+    ErrorCleared,
+
+    /// The front matter metadata of the document changed. The client should update the page title.
+    Metadata { metadata: Option<&'a Metadata> },
+
+    /// The client should scroll to the element rendered from the given line of the markdown.
+    Scroll { line: usize },
+}
+
+/// A message sent from the client to the server.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ClientMessage {
+    Control(ControlMessage),
+    Event(ClientEvent),
+}
+
+/// A message that controls the connection, rather than an event reported to the host application.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ControlMessage {
+    /// The client's document no longer matches the HTML that it was sent, so the full HTML should
+    /// be sent again.
+    Resync,
+}
+
+/// An event reported by a client, such as a click on the rendered preview.
+///
+/// Events are received with [`Server::events`][crate::Server::events].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ClientEvent {
+    /// The user clicked a rendered block element.
+    Click {
+        /// The line of the markdown on which the clicked block begins, numbered from 1.
+        line: usize,
+    },
+
+    /// The user clicked a task list checkbox.
+    ///
+    /// Only reported by renderers with
+    /// [`interactive_tasks`][crate::MarkdownRenderer::interactive_tasks] enabled.
+    TaskToggle {
+        /// The byte offset of the task list marker (such as `[ ]`) in the markdown.
+        offset: usize,
+
+        /// Whether the click requested that the task be checked. The checkbox in the preview does
+        /// not change until the markdown is edited and sent again.
+        checked: bool,
+    },
+}
+
+impl ServerMessage<'_> {
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("message serialization is infallible")
+    }
+}
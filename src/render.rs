@@ -0,0 +1,723 @@
+//! Renderers that convert markdown to HTML.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{iter, mem, panic};
+
+use async_trait::async_trait;
+use pulldown_cmark::escape::{escape_href, escape_html};
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Parser, Tag};
+
+pub use pulldown_cmark::Options;
+
+pub use self::blocks::BlockProcessor;
+pub use self::external::{
+    CommandProcessor, ExitError, ExternalRenderer, Framing, PersistentRenderer,
+};
+pub use self::front_matter::Metadata;
+#[cfg(feature = "syntax-highlighting")]
+pub use self::highlight::{highlight_css, HighlightStyle};
+
+use self::blocks::BlockCache;
+use self::front_matter::FrontMatter;
+#[cfg(feature = "syntax-highlighting")]
+use self::highlight::Highlighter;
+use self::slug::Slugger;
+use crate::{Error, Result};
+
+mod blocks;
+mod external;
+mod front_matter;
+#[cfg(feature = "syntax-highlighting")]
+mod highlight;
+mod mathml;
+mod slug;
+
+/// Converts markdown into HTML.
+///
+/// The server holds a single renderer that is invoked on every call to
+/// [`Server::send`][crate::Server::send]. Implement this trait to plug in a custom renderer.
+///
+/// Custom renderers should report failures with [`Error::Render`][crate::Error::Render].
+///
+/// # Example
+///
+/// ```
+/// use async_trait::async_trait;
+/// use aurelius::{Renderer, Result};
+///
+/// #[derive(Debug)]
+/// struct Preformatted;
+///
+/// #[async_trait]
+/// impl Renderer for Preformatted {
+///     async fn render(&self, markdown: &str, html: &mut String) -> Result<()> {
+///         html.push_str("<pre>");
+///         html.push_str(&markdown.replace('&', "&amp;").replace('<', "&lt;"));
+///         html.push_str("</pre>");
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Renderer: Debug + Send + Sync {
+    /// Renders `markdown` as HTML, appending the result to `html`.
+    async fn render(&self, markdown: &str, html: &mut String) -> Result<()>;
+
+    /// Renders markdown sent to the server, appending the result to `html`, and returns the
+    /// metadata in its front matter.
+    ///
+    /// `deadline` is the instant at which the server's
+    /// [render timeout][crate::Server::set_render_timeout] elapses, if it has one. The server stops
+    /// waiting for the render at the deadline, but work outside of the returned future, such as on
+    /// another thread, continues unless the renderer stops it. The default implementation ignores
+    /// the deadline and calls [`render`][Self::render] and [`metadata`][Self::metadata]. Override
+    /// it to find the metadata while rendering, rather than parsing the markdown twice.
+    async fn render_document(
+        &self,
+        markdown: &str,
+        html: &mut String,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Metadata>> {
+        let _ = deadline;
+        self.render(markdown, html).await?;
+        Ok(self.metadata(markdown))
+    }
+
+    /// Checks that the renderer is usable, such as by checking that an external program exists.
+    ///
+    /// This is called by [`ServerBuilder::bind`][crate::ServerBuilder::bind] so that
+    /// misconfiguration is reported before the server starts. The default implementation always
+    /// succeeds.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the metadata in the front matter of `markdown`, if it has any.
+    ///
+    /// The metadata of the last successful render is used for the title of the preview page, and
+    /// is available to the page template. The default implementation returns `None`.
+    fn metadata(&self, markdown: &str) -> Option<Metadata> {
+        let _ = markdown;
+        None
+    }
+}
+
+/// The default renderer, which uses [`pulldown_cmark`] to render markdown in-process.
+///
+/// `pulldown-cmark` is an extremely fast, [CommonMark]-compliant parser that is sufficient for
+/// most use-cases. By default, footnotes, tables, strikethrough and task lists are enabled. Use
+/// [`options`][Self::options] to choose a different set of extensions.
+///
+/// Block elements are annotated with a `data-source-line` attribute containing the line of the
+/// markdown on which they begin, which is used by [`Server::scroll_to_line`] to scroll the preview.
+///
+/// Headings are given GitHub-compatible ids generated from their text, so that links to sections
+/// such as `[Usage](#usage)` work in the preview, and an anchor link to themselves.
+///
+/// YAML or TOML front matter at the start of the markdown is removed from the rendered HTML, and
+/// its [`Metadata`] is returned along with the HTML by [`Renderer::render_document`], and by
+/// [`Renderer::metadata`]. Invalid front matter is rendered as an
+/// error followed by the front matter.
+///
+/// Rendering runs on tokio's blocking thread pool, so that large documents do not stall the other
+/// tasks on the runtime.
+///
+/// [`Server::scroll_to_line`]: crate::Server::scroll_to_line
+/// [`pulldown_cmark`]: https://github.com/raphlinus/pulldown-cmark
+/// [CommonMark]: https://commonmark.org/
+#[derive(Debug, Clone)]
+pub struct MarkdownRenderer {
+    options: Options,
+    source_lines: bool,
+    interactive_tasks: bool,
+    heading_ids: bool,
+    mathml: bool,
+    front_matter: bool,
+    front_matter_table: bool,
+    processors: HashMap<String, Arc<dyn BlockProcessor>>,
+    block_cache: Arc<Mutex<BlockCache>>,
+    #[cfg(feature = "syntax-highlighting")]
+    highlighter: Option<Arc<Highlighter>>,
+}
+
+impl MarkdownRenderer {
+    /// Creates a new markdown renderer with the default extensions enabled.
+    pub fn new() -> Self {
+        MarkdownRenderer::default()
+    }
+
+    /// Set the markdown extensions that are enabled while parsing.
+    ///
+    /// Pass [`MarkdownOptions::empty()`][Options::empty] to render strict CommonMark.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use aurelius::{MarkdownOptions, MarkdownRenderer};
+    ///
+    /// let renderer = MarkdownRenderer::new().options(
+    ///     MarkdownOptions::ENABLE_TABLES
+    ///         | MarkdownOptions::ENABLE_SMART_PUNCTUATION
+    ///         | MarkdownOptions::ENABLE_HEADING_ATTRIBUTES,
+    /// );
+    /// ```
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set whether block elements are annotated with the line of the markdown on which they begin.
+    ///
+    /// Enabled by default.
+    pub fn source_lines(mut self, enabled: bool) -> Self {
+        self.source_lines = enabled;
+        self
+    }
+
+    /// Set whether task list checkboxes can be clicked in the preview.
+    ///
+    /// When enabled, clicking a checkbox reports a [`ClientEvent::TaskToggle`] containing the byte
+    /// offset of the checkbox's `[ ]` marker in the markdown, which can be received with
+    /// [`Server::events`]. The preview is not updated until the host application edits the
+    /// markdown and sends it again.
+    ///
+    /// Disabled by default, which renders inert checkboxes. Has no effect unless
+    /// [`MarkdownOptions::ENABLE_TASKLISTS`][Options::ENABLE_TASKLISTS] is set.
+    ///
+    /// [`ClientEvent::TaskToggle`]: crate::ClientEvent::TaskToggle
+    /// [`Server::events`]: crate::Server::events
+    pub fn interactive_tasks(mut self, enabled: bool) -> Self {
+        self.interactive_tasks = enabled;
+        self
+    }
+
+    /// Set whether headings are given ids generated from their text, and an anchor link to
+    /// themselves.
+    ///
+    /// Ids are generated the same way as GitHub: the text is lowercased, punctuation is removed,
+    /// and spaces are replaced with `-`. Repeated ids are suffixed with `-1`, `-2`, and so on.
+    /// Headings with an explicit id, set with
+    /// [`MarkdownOptions::ENABLE_HEADING_ATTRIBUTES`][Options::ENABLE_HEADING_ATTRIBUTES], keep it.
+    /// The anchor is an empty `a` element with the `anchor` class at the start of the heading.
+    ///
+    /// Enabled by default.
+    pub fn heading_ids(mut self, enabled: bool) -> Self {
+        self.heading_ids = enabled;
+        self
+    }
+
+    /// Set whether TeX math in `$...$` and `$$...$$` is converted to MathML while rendering.
+    ///
+    /// Math is rendered without any JavaScript, so it also appears in exported HTML and in clients
+    /// without JavaScript. Dollar signs inside code, inline HTML, or escaped with a backslash are
+    /// left as they are, and the opening `$` of inline math must not be followed by a space. Only
+    /// a common subset of TeX is supported: math that cannot be converted is rendered as a `code`
+    /// element with the `math-error` class, with the error as its title.
+    ///
+    /// Disabled by default. When enabled, consider disabling client-side math with
+    /// [`Server::set_math`][crate::Server::set_math].
+    pub fn mathml(mut self, enabled: bool) -> Self {
+        self.mathml = enabled;
+        self
+    }
+
+    /// Set whether YAML or TOML front matter at the start of the markdown is parsed as
+    /// [`Metadata`].
+    ///
+    /// Enabled by default. When disabled, front matter is rendered as markdown.
+    pub fn front_matter(mut self, enabled: bool) -> Self {
+        self.front_matter = enabled;
+        self
+    }
+
+    /// Set whether the front matter metadata is shown as a table at the top of the rendered HTML.
+    ///
+    /// The table has the `front-matter` class. Disabled by default.
+    pub fn front_matter_table(mut self, enabled: bool) -> Self {
+        self.front_matter_table = enabled;
+        self
+    }
+
+    /// Convert fenced code blocks with the language `lang` to HTML with `processor`.
+    ///
+    /// This can be used to render diagrams with local tools, using [`CommandProcessor`]. The output
+    /// of each block is cached by a hash of its contents, so unchanged blocks are not processed
+    /// again on every render. If a block cannot be processed, the error is rendered in its place,
+    /// followed by the contents of the block.
+    ///
+    /// Processors take precedence over server-side syntax highlighting.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use aurelius::{CommandProcessor, MarkdownRenderer};
+    ///
+    /// let mut dot = Command::new("dot");
+    /// dot.arg("-Tsvg");
+    ///
+    /// let renderer = MarkdownRenderer::new().block_processor("dot", CommandProcessor::new(dot));
+    /// ```
+    pub fn block_processor(
+        mut self,
+        lang: impl Into<String>,
+        processor: impl BlockProcessor + 'static,
+    ) -> Self {
+        self.processors.insert(lang.into(), Arc::new(processor));
+
+        // Output cached from a previous processor for the language is no longer valid.
+        self.block_cache = Arc::default();
+        self
+    }
+
+    /// Highlight fenced code blocks while rendering, instead of in the browser.
+    ///
+    /// Code blocks whose language is recognized by one of the embedded syntaxes are rendered with
+    /// highlighted spans styled according to `style`, so exported HTML and clients without
+    /// JavaScript are highlighted too. The browser does not highlight these code blocks again.
+    /// Other code blocks are left for the browser to highlight.
+    ///
+    /// Loading the embedded syntaxes is relatively expensive, so the renderer should be created
+    /// once and reused. An unknown theme is reported by [`validate`][Renderer::validate].
+    ///
+    /// Requires the `syntax-highlighting` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use aurelius::{HighlightStyle, MarkdownRenderer};
+    ///
+    /// let renderer = MarkdownRenderer::new()
+    ///     .syntax_highlighting(HighlightStyle::Inline(String::from("InspiredGitHub")));
+    /// ```
+    #[cfg(feature = "syntax-highlighting")]
+    pub fn syntax_highlighting(mut self, style: HighlightStyle) -> Self {
+        self.highlighter = Some(Arc::new(Highlighter::new(style)));
+        self
+    }
+}
+
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        MarkdownRenderer {
+            options: Options::ENABLE_FOOTNOTES
+                | Options::ENABLE_TABLES
+                | Options::ENABLE_STRIKETHROUGH
+                | Options::ENABLE_TASKLISTS,
+            source_lines: true,
+            interactive_tasks: false,
+            heading_ids: true,
+            mathml: false,
+            front_matter: true,
+            front_matter_table: false,
+            processors: HashMap::new(),
+            block_cache: Arc::default(),
+            #[cfg(feature = "syntax-highlighting")]
+            highlighter: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Renderer for MarkdownRenderer {
+    async fn render(&self, markdown: &str, html: &mut String) -> Result<()> {
+        self.render_document(markdown, html, None).await?;
+        Ok(())
+    }
+
+    async fn render_document(
+        &self,
+        markdown: &str,
+        html: &mut String,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Metadata>> {
+        let renderer = self.clone();
+        let markdown = markdown.to_owned();
+        let mut output = mem::take(html);
+
+        let res = tokio::task::spawn_blocking(move || {
+            let metadata = renderer.push_html(&markdown, &mut output, deadline);
+            (output, metadata)
+        })
+        .await;
+
+        match res {
+            Ok((output, metadata)) => {
+                *html = output;
+                Ok(metadata)
+            }
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::Render(Box::new(e))),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for processor in self.processors.values() {
+            processor.validate()?;
+        }
+
+        #[cfg(feature = "syntax-highlighting")]
+        if let Some(highlighter) = &self.highlighter {
+            highlighter.validate()?;
+        }
+
+        Ok(())
+    }
+
+    fn metadata(&self, markdown: &str) -> Option<Metadata> {
+        if !self.front_matter {
+            return None;
+        }
+
+        FrontMatter::find(markdown)?.metadata.ok()
+    }
+}
+
+impl MarkdownRenderer {
+    /// Renders `markdown` synchronously, appending the result to `html`, and returns the metadata
+    /// in its front matter.
+    ///
+    /// Block processors are passed `deadline`, so that they can stop at the render timeout.
+    fn push_html(
+        &self,
+        markdown: &str,
+        html: &mut String,
+        deadline: Option<Instant>,
+    ) -> Option<Metadata> {
+        let mut source_map = SourceMap::default();
+        let mut body_start = 0;
+        let mut metadata = None;
+
+        let front_matter = if self.front_matter {
+            FrontMatter::find(markdown)
+        } else {
+            None
+        };
+
+        if let Some(front_matter) = front_matter {
+            match front_matter.metadata {
+                Ok(front_matter) => {
+                    if self.front_matter_table {
+                        html.push_str(&front_matter.to_table());
+                    }
+                    metadata = Some(front_matter);
+                }
+                Err(e) => html.push_str(&block_error(
+                    &format!("invalid front matter: {}", e),
+                    front_matter.raw,
+                    "",
+                )),
+            }
+
+            body_start = front_matter.len;
+            source_map.push(0..0, 0..body_start);
+        }
+
+        let source = if self.mathml {
+            let mut source = String::with_capacity(markdown.len() - body_start);
+            mathml::replace_math(
+                markdown,
+                body_start,
+                self.options,
+                &mut source,
+                &mut source_map,
+            );
+            Cow::Owned(source)
+        } else {
+            Cow::Borrowed(&markdown[body_start..])
+        };
+
+        let parser = Parser::new_ext(&source, self.options);
+
+        if self.source_lines
+            || self.interactive_tasks
+            || self.heading_ids
+            || !self.processors.is_empty()
+            || self.highlights()
+        {
+            pulldown_cmark::html::push_html(
+                html,
+                self.annotate(markdown, source_map, parser, deadline),
+            );
+        } else {
+            pulldown_cmark::html::push_html(html, parser);
+        }
+
+        metadata
+    }
+
+    /// Returns whether code blocks are highlighted while rendering.
+    fn highlights(&self) -> bool {
+        #[cfg(feature = "syntax-highlighting")]
+        return self.highlighter.is_some();
+
+        #[cfg(not(feature = "syntax-highlighting"))]
+        false
+    }
+
+    /// Replaces the opening tags of block elements and task list markers with HTML annotated with
+    /// their position in the markdown, the opening tags of headings with tags that have ids, and
+    /// processed and highlighted code blocks with their HTML.
+    ///
+    /// `parser` may parse markdown in which math was replaced, in which case `source_map` maps its
+    /// offsets back to `markdown`.
+    ///
+    /// Tables and footnote definitions are left unannotated, since the HTML writer tracks state
+    /// when opening them.
+    fn annotate<'a>(
+        &self,
+        markdown: &str,
+        source_map: SourceMap,
+        parser: Parser<'a, 'a>,
+        deadline: Option<Instant>,
+    ) -> impl Iterator<Item = Event<'a>> {
+        let source_lines = self.source_lines;
+        let interactive_tasks = self.interactive_tasks;
+        let heading_ids = self.heading_ids;
+        let processors = self.processors.clone();
+        let block_cache = Arc::clone(&self.block_cache);
+        #[cfg(feature = "syntax-highlighting")]
+        let highlighter = self.highlighter.clone();
+
+        let line_starts = iter::once(0)
+            .chain(markdown.match_indices('\n').map(|(i, _)| i + 1))
+            .collect::<Vec<_>>();
+
+        let mut events = parser.into_offset_iter();
+        let mut slugger = Slugger::default();
+        let mut pending = VecDeque::new();
+
+        iter::from_fn(move || {
+            if let Some(event) = pending.pop_front() {
+                return Some(event);
+            }
+
+            let (event, range) = events.next()?;
+            let offset = source_map.to_original(range.start);
+            let line = line_starts.partition_point(|&start| start <= offset);
+
+            if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) = &event {
+                let lang = info.split(' ').next().unwrap();
+                let attrs = || {
+                    if source_lines {
+                        format!(r#" data-source-line="{}""#, line)
+                    } else {
+                        String::new()
+                    }
+                };
+
+                if let Some(processor) = processors.get(lang) {
+                    let code = code_block_text(&mut events);
+                    let html = match BlockCache::process(
+                        &block_cache,
+                        &**processor,
+                        lang,
+                        &code,
+                        deadline,
+                    ) {
+                        Ok(output) => {
+                            format!(
+                                "<div class=\"processed-block\"{}>{}</div>\n",
+                                attrs(),
+                                output
+                            )
+                        }
+                        Err(e) => block_error(&e, &code, &attrs()),
+                    };
+                    return Some(Event::Html(html.into()));
+                }
+
+                #[cfg(feature = "syntax-highlighting")]
+                if let Some(highlighter) = &highlighter {
+                    if let Some(syntax) = highlighter.find_syntax(lang) {
+                        let code = code_block_text(&mut events);
+                        let html = highlighter.highlight(syntax, lang, &code, &attrs());
+                        return Some(Event::Html(html.into()));
+                    }
+                }
+            }
+
+            if let Event::Start(Tag::Heading(level, id, classes)) = &event {
+                if heading_ids || source_lines {
+                    // The id is generated from the text of the heading, so its contents are read
+                    // ahead and emitted after the opening tag.
+                    let mut text = String::new();
+                    for (event, _) in events.by_ref() {
+                        if let Event::Text(t) | Event::Code(t) = &event {
+                            text.push_str(t);
+                        }
+                        let end = matches!(event, Event::End(Tag::Heading(..)));
+                        pending.push_back(event);
+                        if end {
+                            break;
+                        }
+                    }
+
+                    let id = match id {
+                        Some(id) => {
+                            slugger.reserve(id);
+                            Some(id.to_string())
+                        }
+                        None if heading_ids => {
+                            Some(slugger.slug(&text)).filter(|id| !id.is_empty())
+                        }
+                        None => None,
+                    };
+
+                    let html = heading_start_tag(
+                        *level,
+                        id.as_deref(),
+                        classes,
+                        source_lines.then_some(line),
+                        heading_ids,
+                    );
+                    return Some(Event::Html(html.into()));
+                }
+            }
+
+            let html = match event {
+                Event::TaskListMarker(checked) if interactive_tasks => {
+                    Some(task_checkbox(checked, offset))
+                }
+                _ if source_lines => block_start_tag(&event, line),
+                _ => None,
+            };
+
+            match html {
+                Some(html) => Some(Event::Html(html.into())),
+                None => Some(event),
+            }
+        })
+    }
+}
+
+/// Maps offsets in markdown in which spans were replaced, such as front matter or math, back to
+/// offsets in the original markdown.
+#[derive(Debug, Default)]
+struct SourceMap {
+    /// The replaced spans, as ranges in the replaced and original markdown.
+    replacements: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl SourceMap {
+    /// Records that the span `original` of the original markdown was replaced by the span
+    /// `replaced`. Spans must be recorded in order.
+    fn push(&mut self, replaced: Range<usize>, original: Range<usize>) {
+        self.replacements.push((replaced, original));
+    }
+
+    /// Returns the offset in the original markdown corresponding to `offset` in the replaced
+    /// markdown. Offsets within a replacement map to the start of the replaced span.
+    fn to_original(&self, offset: usize) -> usize {
+        let i = self
+            .replacements
+            .partition_point(|(replaced, _)| replaced.start <= offset);
+
+        match i.checked_sub(1).map(|i| &self.replacements[i]) {
+            None => offset,
+            Some((replaced, original)) if offset < replaced.end => original.start,
+            Some((replaced, original)) => original.end + (offset - replaced.end),
+        }
+    }
+}
+
+/// Consumes the events of a code block after its start, returning its text.
+fn code_block_text<'a>(events: &mut impl Iterator<Item = (Event<'a>, Range<usize>)>) -> String {
+    // Code blocks contain only text, followed by the end of the block.
+    let mut code = String::new();
+    for (event, _) in events {
+        match event {
+            Event::Text(text) => code.push_str(&text),
+            _ => break,
+        }
+    }
+    code
+}
+
+/// Returns the HTML shown in place of a code block that could not be processed.
+fn block_error(error: &str, code: &str, attrs: &str) -> String {
+    let mut html = format!(r#"<div class="block-error"{}><p>"#, attrs);
+    escape_html(&mut html, error).unwrap();
+    html.push_str("</p><pre><code>");
+    escape_html(&mut html, code).unwrap();
+    html.push_str("</code></pre></div>\n");
+    html
+}
+
+/// Returns an enabled checkbox annotated with the offset of its task list marker.
+fn task_checkbox(checked: bool, offset: usize) -> String {
+    format!(
+        "<input type=\"checkbox\" data-source-offset=\"{}\"{}/>\n",
+        offset,
+        if checked { " checked=\"\"" } else { "" },
+    )
+}
+
+/// Returns the opening tag of a heading, optionally annotated with its source line and followed by
+/// an anchor link to the heading.
+fn heading_start_tag(
+    level: HeadingLevel,
+    id: Option<&str>,
+    classes: &[&str],
+    line: Option<usize>,
+    anchor: bool,
+) -> String {
+    let mut html = format!("<{}", level);
+    if let Some(id) = id {
+        html.push_str(r#" id=""#);
+        escape_html(&mut html, id).unwrap();
+        html.push('"');
+    }
+    if !classes.is_empty() {
+        html.push_str(r#" class=""#);
+        escape_html(&mut html, &classes.join(" ")).unwrap();
+        html.push('"');
+    }
+    if let Some(line) = line {
+        write!(html, r#" data-source-line="{}""#, line).unwrap();
+    }
+    html.push('>');
+    if let (Some(id), true) = (id, anchor) {
+        html.push_str(r##"<a class="anchor" href="#"##);
+        escape_href(&mut html, id).unwrap();
+        html.push_str(r#"" aria-hidden="true"></a>"#);
+    }
+    html
+}
+
+/// Returns the opening tag of a block element, annotated with its source line.
+fn block_start_tag(event: &Event<'_>, line: usize) -> Option<String> {
+    let attr = format!(r#" data-source-line="{}""#, line);
+
+    let html = match event {
+        Event::Start(Tag::Paragraph) => format!("<p{}>", attr),
+        Event::Start(Tag::BlockQuote) => format!("<blockquote{}>\n", attr),
+        Event::Start(Tag::CodeBlock(kind)) => {
+            let lang = match kind {
+                CodeBlockKind::Fenced(info) => info.split(' ').next().unwrap(),
+                CodeBlockKind::Indented => "",
+            };
+            if lang.is_empty() {
+                format!("<pre{}><code>", attr)
+            } else {
+                let mut html = format!(r#"<pre{}><code class="language-"#, attr);
+                escape_html(&mut html, lang).unwrap();
+                html.push_str(r#"">"#);
+                html
+            }
+        }
+        Event::Start(Tag::List(None)) => format!("<ul{}>\n", attr),
+        Event::Start(Tag::List(Some(1))) => format!("<ol{}>\n", attr),
+        Event::Start(Tag::List(Some(start))) => format!(r#"<ol start="{}"{}>\n"#, start, attr),
+        Event::Start(Tag::Item) => format!("<li{}>", attr),
+        Event::Rule => format!("<hr{} />\n", attr),
+        _ => return None,
+    };
+
+    Some(html)
+}
@@ -0,0 +1,128 @@
+//! Fenced code blocks that are converted to HTML by processors, such as diagrams rendered by
+//! local tools.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::error::display_chain;
+use crate::Result;
+
+/// The maximum number of processed blocks that are cached.
+const CACHE_CAPACITY: usize = 256;
+
+/// Converts the contents of fenced code blocks to HTML.
+///
+/// Processors are registered for a code block language with
+/// [`MarkdownRenderer::block_processor`][super::MarkdownRenderer::block_processor]. Use
+/// [`CommandProcessor`][super::CommandProcessor] to convert blocks with a local program.
+pub trait BlockProcessor: Debug + Send + Sync {
+    /// Converts the contents of a code block to HTML.
+    ///
+    /// This is called on tokio's blocking thread pool, so it may block. Errors are rendered in
+    /// place of the block, rather than failing the entire render.
+    ///
+    /// `deadline` is the instant at which the server's
+    /// [render timeout][crate::Server::set_render_timeout] elapses, if it has one. Processors that
+    /// may run for a long time should give up at the deadline and return
+    /// [`Error::RendererTimeout`][crate::Error::RendererTimeout].
+    fn process(&self, code: &str, deadline: Option<Instant>) -> Result<String>;
+
+    /// Checks that the processor is usable, such as by checking that an external program exists.
+    ///
+    /// This is called by [`MarkdownRenderer`][super::MarkdownRenderer]'s implementation of
+    /// [`Renderer::validate`][super::Renderer::validate]. The default implementation always
+    /// succeeds.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Caches the output of block processors by the language and contents of each block, so that
+/// unchanged blocks are not processed again on every render.
+///
+/// Only successful output is cached, so that blocks that failed, such as because a processor timed
+/// out, are retried on the next render.
+#[derive(Debug, Default)]
+pub(crate) struct BlockCache {
+    /// Entries by a hash of their language and contents.
+    entries: HashMap<u64, Entry>,
+
+    /// Incremented on every lookup, to find the least recently used entry.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    lang: String,
+    code: String,
+    html: String,
+    last_used: u64,
+}
+
+impl BlockCache {
+    /// Returns the cached output of `processor` for a block, processing it on a cache miss.
+    pub(crate) fn process(
+        cache: &Mutex<Self>,
+        processor: &dyn BlockProcessor,
+        lang: &str,
+        code: &str,
+        deadline: Option<Instant>,
+    ) -> Result<String, String> {
+        let mut hasher = DefaultHasher::new();
+        (lang, code).hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(html) = cache.lock().unwrap().get(key, lang, code) {
+            return Ok(html);
+        }
+
+        // The lock is not held while processing, so that other documents can be rendered.
+        let html = processor
+            .process(code, deadline)
+            .map_err(|e| display_chain(&e))?;
+        cache.lock().unwrap().insert(key, lang, code, html.clone());
+        Ok(html)
+    }
+
+    fn get(&mut self, key: u64, lang: &str, code: &str) -> Option<String> {
+        self.clock += 1;
+
+        let entry = self.entries.get_mut(&key)?;
+
+        // Blocks whose hashes collide are not cached at the same time.
+        if entry.lang != lang || entry.code != code {
+            return None;
+        }
+
+        entry.last_used = self.clock;
+        Some(entry.html.clone())
+    }
+
+    fn insert(&mut self, key: u64, lang: &str, code: &str, html: String) {
+        if self.entries.len() >= CACHE_CAPACITY && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(&key, _)| key);
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                lang: lang.to_owned(),
+                code: code.to_owned(),
+                html,
+                last_used: self.clock,
+            },
+        );
+    }
+}
@@ -0,0 +1,633 @@
+//! Renderers and block processors backed by external programs.
+
+use std::env;
+use std::error;
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::panic;
+use std::path::Path;
+use std::process::{self, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task::JoinHandle;
+use tracing::log::*;
+
+use super::{BlockProcessor, Metadata, Renderer};
+use crate::{Error, Result};
+
+/// The maximum number of bytes of stderr that are retained from an external renderer.
+const MAX_STDERR_LEN: usize = 64 * 1024;
+
+/// How often a [`CommandProcessor`] checks whether its program has exited before the deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An error indicating that an external program exited unsuccessfully.
+///
+/// Returned by external renderers in [`Error::RendererExit`], and by [`CommandProcessor`] in
+/// [`Error::ProcessorExit`].
+#[derive(Debug)]
+pub struct ExitError {
+    program: String,
+    status: ExitStatus,
+    stderr: String,
+}
+
+impl ExitError {
+    fn new(program: &str, status: ExitStatus, stderr: &[u8]) -> Self {
+        ExitError {
+            program: program.to_owned(),
+            status,
+            stderr: String::from_utf8_lossy(tail(stderr)).into_owned(),
+        }
+    }
+
+    /// The program that exited, as it was passed to `Command::new`.
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// The exit status of the program.
+    pub fn status(&self) -> ExitStatus {
+        self.status
+    }
+
+    /// The exit code of the program, if it was not terminated by a signal.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// The output that the program wrote to stderr.
+    ///
+    /// Only the last 64KiB of output are retained.
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+impl Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed with {}", self.program, self.status)?;
+
+        let stderr = self.stderr.trim();
+        if !stderr.is_empty() {
+            write!(f, ": {}", stderr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for ExitError {}
+
+/// A renderer that delegates to an external program.
+///
+/// The program is spawned once per render. It should expect markdown on stdin and print HTML on
+/// stdout. If the program exits unsuccessfully, the render fails with an [`ExitError`] containing
+/// the program's stderr.
+#[derive(Debug)]
+pub struct ExternalRenderer {
+    program: String,
+    command: Mutex<Command>,
+}
+
+impl ExternalRenderer {
+    /// Creates a renderer that spawns `command` to render markdown.
+    pub fn new(mut command: Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        ExternalRenderer {
+            program: program_name(command.as_std()),
+            command: Mutex::new(command),
+        }
+    }
+}
+
+#[async_trait]
+impl Renderer for ExternalRenderer {
+    async fn render(&self, markdown: &str, html: &mut String) -> Result<()> {
+        let mut child = self
+            .command
+            .lock()
+            .unwrap()
+            .spawn()
+            .map_err(Error::RendererSpawn)?;
+
+        // Write stdin while reading the output to avoid filling the pipes.
+        let mut stdin = child.stdin.take().unwrap();
+        let write = async move {
+            stdin.write_all(markdown.as_bytes()).await
+            // stdin is dropped here, signalling EOF.
+        };
+
+        let (write, output) = tokio::join!(write, child.wait_with_output());
+        let output = output.map_err(render_error)?;
+
+        if !output.status.success() {
+            return Err(ExitError::new(&self.program, output.status, &output.stderr).into());
+        }
+
+        write.map_err(render_error)?;
+
+        let stdout = std::str::from_utf8(&output.stdout).map_err(render_error)?;
+        html.push_str(stdout);
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_program(self.command.lock().unwrap().as_std()).map_err(Error::RendererSpawn)
+    }
+}
+
+/// How documents are delimited on the stdin and stdout of a [`PersistentRenderer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Each document is preceded by its length in bytes, encoded as a 32-bit big-endian unsigned
+    /// integer.
+    LengthPrefixed,
+
+    /// Each document is terminated by a NUL byte. Documents must not contain NUL bytes.
+    NulDelimited,
+}
+
+/// A renderer that keeps a single external program alive across renders.
+///
+/// Spawning a process for every render can be expensive for renderers with a long startup time.
+/// Instead, this renderer writes each markdown document to the stdin of a long-lived child
+/// process, framed according to its [`Framing`], and expects the rendered HTML to be written back
+/// on stdout with the same framing.
+///
+/// The process is spawned lazily on the first render. If the process dies, it is restarted
+/// automatically and the render is retried once. If the restarted process dies as well, the
+/// render fails with an [`ExitError`] containing the process's stderr.
+///
+/// A render that is cancelled, such as because newer markdown was sent to the server, lets the
+/// process finish the document that it is rendering, so that the process is not restarted on
+/// every edit. The process is only killed if it is still rendering when the render times out.
+///
+/// # Example
+///
+/// ```no_run
+/// use tokio::process::Command;
+/// use aurelius::{Framing, PersistentRenderer};
+///
+/// let renderer = PersistentRenderer::new(Command::new("my-renderer"), Framing::NulDelimited);
+/// ```
+#[derive(Debug)]
+pub struct PersistentRenderer {
+    state: Arc<PersistentState>,
+}
+
+/// The state of a [`PersistentRenderer`], which is shared with the tasks that render each
+/// document.
+#[derive(Debug)]
+struct PersistentState {
+    program: String,
+    command: Mutex<Command>,
+    framing: Framing,
+    process: AsyncMutex<Option<Process>>,
+}
+
+impl PersistentRenderer {
+    /// Creates a renderer that communicates with `command` using the specified framing.
+    pub fn new(mut command: Command, framing: Framing) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        PersistentRenderer {
+            state: Arc::new(PersistentState {
+                program: program_name(command.as_std()),
+                command: Mutex::new(command),
+                framing,
+                process: AsyncMutex::new(None),
+            }),
+        }
+    }
+
+    async fn render_until(
+        &self,
+        markdown: &str,
+        html: &mut String,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        if self.state.framing == Framing::NulDelimited && markdown.contains('\0') {
+            return Err(Error::Render("markdown contains a NUL byte".into()));
+        }
+
+        let state = Arc::clone(&self.state);
+        let markdown = markdown.to_owned();
+
+        // The document is rendered on its own task, so that cancelling this future does not
+        // interrupt the process in the middle of a frame. The next render waits for the frame to
+        // finish, and its output is discarded.
+        let frame = tokio::spawn(async move {
+            let render = state.render(&markdown);
+
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), render)
+                    .await
+                    .map_err(|_| Error::RendererTimeout)?,
+                None => render.await,
+            }
+        });
+
+        match frame.await {
+            Ok(output) => {
+                html.push_str(&output?);
+                Ok(())
+            }
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::Render(Box::new(e))),
+        }
+    }
+}
+
+impl PersistentState {
+    fn spawn(&self) -> Result<Process> {
+        let mut command = self.command.lock().unwrap();
+        info!("spawning persistent renderer: {:?}", command);
+        let mut child = command.spawn().map_err(Error::RendererSpawn)?;
+
+        Ok(Process {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            stderr: tokio::spawn(collect_stderr(child.stderr.take().unwrap())),
+            child,
+        })
+    }
+
+    async fn render(&self, markdown: &str) -> Result<String> {
+        let mut slot = self.process.lock().await;
+        let mut html = String::new();
+        let mut restarted = false;
+
+        loop {
+            // The process is taken out of the slot while a render is in progress, so that a render
+            // that times out drops (and kills) a process that may be in the middle of a frame.
+            let mut process = match slot.take() {
+                Some(process) => process,
+                None => self.spawn()?,
+            };
+
+            match process.render(self.framing, markdown, &mut html).await {
+                Ok(()) => {
+                    *slot = Some(process);
+                    return Ok(html);
+                }
+                Err(e) if is_disconnect(&e) => {
+                    let e = process.exit_error(&self.program, e).await;
+
+                    if restarted {
+                        return Err(e);
+                    }
+
+                    warn!("persistent renderer exited, restarting: {}", e);
+                    html.clear();
+                    restarted = true;
+                }
+                Err(e) => return Err(render_error(e)),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Renderer for PersistentRenderer {
+    async fn render(&self, markdown: &str, html: &mut String) -> Result<()> {
+        self.render_until(markdown, html, None).await
+    }
+
+    async fn render_document(
+        &self,
+        markdown: &str,
+        html: &mut String,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Metadata>> {
+        self.render_until(markdown, html, deadline).await?;
+        Ok(None)
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_program(self.state.command.lock().unwrap().as_std()).map_err(Error::RendererSpawn)
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: JoinHandle<Vec<u8>>,
+    child: Child,
+}
+
+impl Process {
+    /// Converts an I/O error caused by the process exiting into an [`ExitError`], if the process
+    /// exited unsuccessfully.
+    async fn exit_error(self, program: &str, e: io::Error) -> Error {
+        let Process {
+            stdin,
+            mut child,
+            stderr,
+            ..
+        } = self;
+
+        drop(stdin);
+
+        // The process may have closed its pipes without exiting.
+        let status = match tokio::time::timeout(Duration::from_secs(1), child.wait()).await {
+            Ok(Ok(status)) => status,
+            _ => return render_error(e),
+        };
+
+        if status.success() {
+            return render_error(e);
+        }
+
+        let stderr = stderr.await.unwrap_or_default();
+
+        ExitError::new(program, status, &stderr).into()
+    }
+
+    async fn render(
+        &mut self,
+        framing: Framing,
+        markdown: &str,
+        html: &mut String,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+
+        match framing {
+            Framing::LengthPrefixed => {
+                let len = u32::try_from(markdown.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "markdown is too large")
+                })?;
+                self.stdin.write_u32(len).await?;
+                self.stdin.write_all(markdown.as_bytes()).await?;
+                self.stdin.flush().await?;
+
+                let len = self.stdout.read_u32().await?;
+                buf.resize(len as usize, 0);
+                self.stdout.read_exact(&mut buf).await?;
+            }
+            Framing::NulDelimited => {
+                self.stdin.write_all(markdown.as_bytes()).await?;
+                self.stdin.write_all(b"\0").await?;
+                self.stdin.flush().await?;
+
+                self.stdout.read_until(b'\0', &mut buf).await?;
+                if buf.pop() != Some(b'\0') {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+
+        let output =
+            String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        html.push_str(&output);
+
+        Ok(())
+    }
+}
+
+/// A block processor that delegates to an external program, such as Graphviz or PlantUML.
+///
+/// The program is spawned once for each code block whose output is not already cached. It should
+/// expect the contents of the block on stdin and print SVG or HTML on stdout, which is inlined
+/// into the rendered HTML. An XML declaration or doctype preceding an `<svg>` element is removed.
+/// If the program exits unsuccessfully, an [`ExitError`] containing its stderr is shown in place
+/// of the block. If the program is still running when the render times out, it is killed.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::process::Command;
+/// use aurelius::{CommandProcessor, MarkdownRenderer};
+///
+/// let mut dot = Command::new("dot");
+/// dot.arg("-Tsvg");
+///
+/// let mut plantuml = Command::new("plantuml");
+/// plantuml.args(["-tsvg", "-pipe"]);
+///
+/// let renderer = MarkdownRenderer::new()
+///     .block_processor("dot", CommandProcessor::new(dot))
+///     .block_processor("plantuml", CommandProcessor::new(plantuml));
+/// ```
+#[derive(Debug)]
+pub struct CommandProcessor {
+    program: String,
+    command: Mutex<process::Command>,
+}
+
+impl CommandProcessor {
+    /// Creates a processor that spawns `command` to convert code blocks.
+    pub fn new(mut command: process::Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        CommandProcessor {
+            program: program_name(&command),
+            command: Mutex::new(command),
+        }
+    }
+}
+
+impl BlockProcessor for CommandProcessor {
+    fn process(&self, code: &str, deadline: Option<Instant>) -> Result<String> {
+        let mut child = self
+            .command
+            .lock()
+            .unwrap()
+            .spawn()
+            .map_err(Error::ProcessorSpawn)?;
+
+        // Write stdin and read the output on other threads, so that the pipes do not fill up while
+        // waiting for the program to exit. If the program is killed, the threads are not joined,
+        // since its own children may keep the pipes open.
+        let mut stdin = child.stdin.take().unwrap();
+        let code = code.to_owned();
+        let write = thread::spawn(move || stdin.write_all(code.as_bytes()));
+        let stdout = read_to_end(child.stdout.take().unwrap());
+        let stderr = read_to_end(child.stderr.take().unwrap());
+
+        let status = match wait_until(&mut child, deadline).map_err(render_error)? {
+            Some(status) => status,
+            None => return Err(Error::RendererTimeout),
+        };
+
+        let stdout = stdout.join().unwrap().map_err(render_error)?;
+        let stderr = stderr.join().unwrap().map_err(render_error)?;
+
+        if !status.success() {
+            return Err(Error::ProcessorExit(ExitError::new(
+                &self.program,
+                status,
+                &stderr,
+            )));
+        }
+
+        write.join().unwrap().map_err(render_error)?;
+
+        let stdout = String::from_utf8(stdout).map_err(render_error)?;
+
+        Ok(strip_svg_prolog(&stdout).to_owned())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_program(&self.command.lock().unwrap()).map_err(Error::ProcessorSpawn)
+    }
+}
+
+/// Reads `reader` to completion on another thread.
+fn read_to_end(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        Ok(output)
+    })
+}
+
+/// Waits for `child` to exit, killing it if it is still running at `deadline`.
+///
+/// Returns `None` if the child was killed.
+fn wait_until(
+    child: &mut process::Child,
+    deadline: Option<Instant>,
+) -> io::Result<Option<ExitStatus>> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return child.wait().map(Some),
+    };
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Removes the XML declaration, doctype and comments that precede the `<svg>` element of an SVG
+/// document, which are invalid in HTML.
+fn strip_svg_prolog(output: &str) -> &str {
+    let trimmed = output.trim_start();
+
+    if trimmed.starts_with("<?xml") || trimmed.starts_with("<!DOCTYPE") {
+        if let Some(start) = trimmed.find("<svg") {
+            return &trimmed[start..];
+        }
+    }
+
+    output
+}
+
+/// Checks that the program of `command` is an executable file, either as a path or on `PATH`.
+fn check_program(command: &process::Command) -> io::Result<()> {
+    let program = command.get_program();
+
+    if find_program(program, command.get_current_dir()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("program not found: {}", program.to_string_lossy()),
+        ))
+    }
+}
+
+/// Returns the program of `command`, for error messages.
+fn program_name(command: &process::Command) -> String {
+    command.get_program().to_string_lossy().into_owned()
+}
+
+fn find_program(program: &OsStr, current_dir: Option<&Path>) -> bool {
+    let path = Path::new(program);
+
+    if path.components().count() > 1 {
+        // Relative paths are resolved against the directory that the program is run in.
+        return match current_dir {
+            Some(dir) => is_executable(&dir.join(path)),
+            None => is_executable(path),
+        };
+    }
+
+    let paths = match env::var_os("PATH") {
+        Some(paths) => paths,
+        None => return false,
+    };
+
+    env::split_paths(&paths).any(|dir| {
+        let candidate = dir.join(program);
+        is_executable(&candidate)
+            || (cfg!(windows) && is_executable(&candidate.with_extension("exe")))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Wraps an error that occurred while communicating with an external program.
+fn render_error(e: impl error::Error + Send + Sync + 'static) -> Error {
+    Error::Render(Box::new(e))
+}
+
+/// Reads stderr to completion, retaining only the last [`MAX_STDERR_LEN`] bytes.
+async fn collect_stderr(mut stderr: impl AsyncRead + Unpin) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+
+    while let Ok(n @ 1..) = stderr.read(&mut buf).await {
+        output.extend_from_slice(&buf[..n]);
+        let excess = output.len().saturating_sub(MAX_STDERR_LEN);
+        output.drain(..excess);
+    }
+
+    output
+}
+
+/// Returns the last [`MAX_STDERR_LEN`] bytes of `stderr`.
+fn tail(stderr: &[u8]) -> &[u8] {
+    &stderr[stderr.len().saturating_sub(MAX_STDERR_LEN)..]
+}
+
+/// Returns whether the error indicates that the process exited.
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+    )
+}
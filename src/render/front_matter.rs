@@ -0,0 +1,158 @@
+//! YAML and TOML front matter at the start of markdown documents.
+
+use pulldown_cmark::escape::escape_html;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Metadata parsed from the front matter of a markdown document.
+///
+/// Front matter is a block of YAML delimited by `---` lines, or of TOML delimited by `+++` lines,
+/// at the very start of the document. It must be a mapping of keys to values.
+///
+/// ```markdown
+/// ---
+/// title: Design document
+/// tags: [rendering, preview]
+/// ---
+///
+/// # Overview
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Metadata(Map<String, Value>);
+
+impl Metadata {
+    /// The `title` key of the metadata, if it is a string.
+    ///
+    /// The title is used as the title of the preview page.
+    pub fn title(&self) -> Option<&str> {
+        self.0.get("title")?.as_str()
+    }
+
+    /// Returns the value of a key, if it is present.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    /// Returns an iterator over the keys and values of the metadata, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Returns a table listing the keys and values of the metadata.
+    pub(crate) fn to_table(&self) -> String {
+        let mut html = String::from("<table class=\"front-matter\">\n<tbody>\n");
+
+        for (key, value) in self.iter() {
+            html.push_str("<tr><th>");
+            escape_html(&mut html, key).unwrap();
+            html.push_str("</th><td>");
+            match value {
+                Value::String(value) => escape_html(&mut html, value).unwrap(),
+                value => escape_html(&mut html, &value.to_string()).unwrap(),
+            }
+            html.push_str("</td></tr>\n");
+        }
+
+        html.push_str("</tbody>\n</table>\n");
+        html
+    }
+}
+
+/// The front matter at the start of a markdown document.
+#[derive(Debug)]
+pub(crate) struct FrontMatter<'a> {
+    /// The front matter, excluding its delimiters.
+    pub(crate) raw: &'a str,
+
+    /// The length of the front matter in bytes, including its delimiters.
+    pub(crate) len: usize,
+
+    /// The parsed metadata, or an error message if the front matter is invalid.
+    pub(crate) metadata: Result<Metadata, String>,
+}
+
+impl<'a> FrontMatter<'a> {
+    /// Finds the front matter at the start of `markdown`, if it has any.
+    ///
+    /// Delimited text that is valid YAML or TOML but not a mapping is not considered front matter,
+    /// since it is more likely to be markdown such as a setext heading.
+    ///
+    /// A byte order mark before the opening delimiter is skipped, and counted in the length of the
+    /// front matter.
+    pub(crate) fn find(markdown: &'a str) -> Option<Self> {
+        let bom = if markdown.starts_with('\u{feff}') {
+            '\u{feff}'.len_utf8()
+        } else {
+            0
+        };
+
+        let first_line = markdown[bom..].split_inclusive('\n').next()?;
+
+        let (is_yaml, closing): (_, &[&str]) = match first_line.trim_end() {
+            "---" => (true, &["---", "..."]),
+            "+++" => (false, &["+++"]),
+            _ => return None,
+        };
+
+        // Without a trailing newline, the delimiter is a thematic break or text.
+        if !first_line.ends_with('\n') {
+            return None;
+        }
+
+        let start = bom + first_line.len();
+        let mut pos = start;
+
+        for line in markdown[start..].split_inclusive('\n') {
+            if closing.contains(&line.trim_end()) {
+                let raw = &markdown[start..pos];
+
+                let value = if is_yaml {
+                    serde_yaml::from_str::<Value>(raw).map_err(|e| e.to_string())
+                } else {
+                    toml::from_str::<toml::Value>(raw)
+                        .map(toml_to_json)
+                        .map_err(|e| e.to_string())
+                };
+
+                let metadata = match value {
+                    Ok(Value::Object(map)) => Ok(Metadata(map)),
+                    Ok(Value::Null) => Ok(Metadata::default()),
+                    Ok(_) => return None,
+                    Err(e) => Err(e),
+                };
+
+                return Some(FrontMatter {
+                    raw,
+                    len: pos + line.len(),
+                    metadata,
+                });
+            }
+
+            pos += line.len();
+        }
+
+        None
+    }
+}
+
+/// Converts a TOML value to JSON.
+///
+/// Datetimes are converted to strings in RFC 3339 format, rather than the private representation
+/// that `toml` uses to serialize them. Floats that cannot be represented in JSON become `null`.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
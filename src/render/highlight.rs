@@ -0,0 +1,178 @@
+//! Server-side syntax highlighting of fenced code blocks.
+
+use std::fmt::{self, Debug, Write};
+
+use pulldown_cmark::escape::escape_html;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{self, ClassStyle, ClassedHTMLGenerator, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::{Error, Result};
+
+/// The prefix of the classes emitted by [`HighlightStyle::Classed`].
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// How code highlighted by [`MarkdownRenderer`][super::MarkdownRenderer] is styled.
+///
+/// The available themes are the defaults embedded in [`syntect`], such as `InspiredGitHub`,
+/// `Solarized (light)` and `base16-ocean.dark`.
+///
+/// [`syntect`]: https://github.com/trishume/syntect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HighlightStyle {
+    /// Tokens are wrapped in spans with inline `style` attributes, using the colors of the named
+    /// theme.
+    Inline(String),
+
+    /// Tokens are wrapped in spans with classes prefixed with `hl-`. Use [`highlight_css`] to
+    /// generate a stylesheet for a theme.
+    Classed,
+}
+
+/// Returns a stylesheet for code highlighted with [`HighlightStyle::Classed`].
+///
+/// # Errors
+///
+/// Returns [`Error::Theme`] if the theme does not exist.
+pub fn highlight_css(theme: &str) -> Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let theme = themes
+        .themes
+        .get(theme)
+        .ok_or_else(|| Error::Theme(theme.to_owned()))?;
+
+    html::css_for_theme_with_class_style(theme, CLASS_STYLE).map_err(|e| Error::Render(e.into()))
+}
+
+/// Highlights code blocks with the embedded syntaxes.
+pub(crate) struct Highlighter {
+    style: HighlightStyle,
+    syntaxes: SyntaxSet,
+
+    /// The theme for [`HighlightStyle::Inline`], if it exists.
+    theme: Option<Theme>,
+}
+
+impl Highlighter {
+    pub(crate) fn new(style: HighlightStyle) -> Self {
+        let theme = match &style {
+            HighlightStyle::Inline(theme) => ThemeSet::load_defaults().themes.remove(theme),
+            HighlightStyle::Classed => None,
+        };
+
+        Highlighter {
+            style,
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+        }
+    }
+
+    /// Returns an error if the theme does not exist.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match &self.style {
+            HighlightStyle::Inline(theme) if self.theme.is_none() => {
+                Err(Error::Theme(theme.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the syntax for a code block's language, if it is known.
+    pub(crate) fn find_syntax(&self, lang: &str) -> Option<&SyntaxReference> {
+        if lang.is_empty() {
+            return None;
+        }
+
+        self.syntaxes.find_syntax_by_token(lang)
+    }
+
+    /// Renders a highlighted code block. `attrs` are added to the `pre` element.
+    pub(crate) fn highlight(
+        &self,
+        syntax: &SyntaxReference,
+        lang: &str,
+        code: &str,
+        attrs: &str,
+    ) -> String {
+        let mut html = format!("<pre{}", attrs);
+
+        let highlighted = match (&self.style, &self.theme) {
+            (HighlightStyle::Inline(_), Some(theme)) => {
+                if let Some(bg) = theme.settings.background {
+                    write!(
+                        html,
+                        r#" style="background-color:#{:02x}{:02x}{:02x};""#,
+                        bg.r, bg.g, bg.b
+                    )
+                    .unwrap();
+                }
+                self.highlight_inline(syntax, theme, code)
+            }
+            (HighlightStyle::Inline(_), None) => None,
+            (HighlightStyle::Classed, _) => self.highlight_classed(syntax, code),
+        };
+
+        html.push_str(r#"><code class="language-"#);
+        escape_html(&mut html, lang).unwrap();
+
+        // Leave code that failed to highlight for the browser.
+        match highlighted {
+            Some(highlighted) => {
+                html.push_str(r#"" data-highlighted="yes">"#);
+                html.push_str(&highlighted);
+            }
+            None => {
+                html.push_str(r#"">"#);
+                escape_html(&mut html, code).unwrap();
+            }
+        }
+
+        html.push_str("</code></pre>\n");
+        html
+    }
+
+    fn highlight_inline(
+        &self,
+        syntax: &SyntaxReference,
+        theme: &Theme,
+        code: &str,
+    ) -> Option<String> {
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut html = String::new();
+
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter.highlight_line(line, &self.syntaxes).ok()?;
+            html::append_highlighted_html_for_styled_line(
+                &regions,
+                IncludeBackground::No,
+                &mut html,
+            )
+            .ok()?;
+        }
+
+        Some(html)
+    }
+
+    fn highlight_classed(&self, syntax: &SyntaxReference, code: &str) -> Option<String> {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+
+        Some(generator.finalize())
+    }
+}
+
+impl Debug for Highlighter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Highlighter")
+            .field("style", &self.style)
+            .finish_non_exhaustive()
+    }
+}
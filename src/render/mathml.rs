@@ -0,0 +1,1025 @@
+//! Conversion of TeX math spans in markdown to MathML.
+//!
+//! Math spans are replaced in the markdown source before it is parsed, so that characters such as
+//! `_` and `*` inside of math are not interpreted as markdown. The replacement HTML encodes all
+//! ASCII punctuation in text as character references for the same reason. A [`SourceMap`] maps
+//! offsets in the replaced source back to the original markdown.
+//!
+//! Only a commonly used subset of TeX is supported: identifiers, numbers and operators,
+//! superscripts and subscripts, fractions, roots, greek letters and other symbols, functions,
+//! accents, font styles, text, spacing, `\left` and `\right`, and matrix-like environments.
+//!
+//! [`SourceMap`]: super::SourceMap
+
+use std::fmt::{self, Display, Write};
+use std::ops::Range;
+
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use super::SourceMap;
+
+/// Appends the markdown after `start` to `source`, replacing `$...$` and `$$...$$` spans outside
+/// of code and HTML with MathML and recording the replacements in `map`.
+///
+/// Malformed TeX is replaced with a `code` element with the `math-error` class, containing the
+/// TeX and with the error as its title.
+pub(crate) fn replace_math(
+    markdown: &str,
+    start: usize,
+    options: Options,
+    source: &mut String,
+    map: &mut SourceMap,
+) {
+    let skipped = skipped_ranges(&markdown[start..], options);
+    let is_skipped = |offset: usize| {
+        let offset = offset - start;
+        let i = skipped.partition_point(|range| range.start <= offset);
+        i > 0 && offset < skipped[i - 1].end
+    };
+
+    let mut copied = start;
+    let mut pos = start;
+
+    while let Some(i) = markdown[pos..].find('$') {
+        let open = pos + i;
+        pos = open + 1;
+
+        if is_skipped(open) || is_escaped(markdown, open) {
+            continue;
+        }
+
+        let (span, display) = match find_math(markdown, open, &is_skipped) {
+            Some(span) => span,
+            None => continue,
+        };
+
+        let delimiter_len = if display { 2 } else { 1 };
+        let tex = &markdown[span.start + delimiter_len..span.end - delimiter_len];
+
+        source.push_str(&markdown[copied..span.start]);
+        let replaced_start = source.len();
+
+        match tex_to_mathml(tex, display) {
+            Ok(mathml) => source.push_str(&mathml),
+            Err(e) => {
+                source.push_str(r#"<code class="math-error" title=""#);
+                escape_html(&mut *source, &e.to_string()).unwrap();
+                source.push_str(r#"">"#);
+                push_text(source, &markdown[span.clone()]);
+                source.push_str("</code>");
+            }
+        }
+
+        map.push(replaced_start..source.len(), span.clone());
+        copied = span.end;
+        pos = span.end;
+    }
+
+    source.push_str(&markdown[copied..]);
+}
+
+/// Returns the sorted ranges of code and HTML in the markdown, where math is not recognized.
+fn skipped_ranges(markdown: &str, options: Options) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+
+    for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+        if let Event::Code(_) | Event::Html(_) | Event::Start(Tag::CodeBlock(_)) = event {
+            // Code blocks are reported before the code that they contain.
+            match ranges.last() {
+                Some(last) if last.end >= range.end => {}
+                _ => ranges.push(range),
+            }
+        }
+    }
+
+    ranges
+}
+
+/// Returns whether the character at `offset` is escaped with a backslash.
+fn is_escaped(markdown: &str, offset: usize) -> bool {
+    let backslashes = markdown[..offset]
+        .bytes()
+        .rev()
+        .take_while(|&b| b == b'\\')
+        .count();
+    backslashes % 2 == 1
+}
+
+/// Finds the math span starting at the `$` at `start`, and whether it is display math.
+///
+/// Like pandoc, the opening `$` of inline math must be followed by a non-space character, and
+/// the closing `$` must be preceded by a non-space character and not followed by a digit. Math
+/// cannot span a blank line.
+fn find_math(
+    markdown: &str,
+    start: usize,
+    is_skipped: &impl Fn(usize) -> bool,
+) -> Option<(Range<usize>, bool)> {
+    let display = markdown[start..].starts_with("$$");
+    let delimiter = if display { "$$" } else { "$" };
+    let content_start = start + delimiter.len();
+
+    let first = markdown[content_start..].chars().next()?;
+    if !display && first.is_whitespace() {
+        return None;
+    }
+
+    let mut pos = content_start;
+    while let Some(i) = markdown[pos..].find(delimiter) {
+        let end = pos + i;
+        pos = end + 1;
+
+        if is_blank_line_between(&markdown[content_start..end]) {
+            return None;
+        }
+
+        if end == content_start || is_skipped(end) || is_escaped(markdown, end) {
+            continue;
+        }
+
+        if !display {
+            let before = markdown[..end].chars().next_back()?;
+            let after = markdown[end + 1..].chars().next();
+            if before.is_whitespace() || after.is_some_and(|c| c.is_ascii_digit()) {
+                continue;
+            }
+        }
+
+        return Some((start..end + delimiter.len(), display));
+    }
+
+    None
+}
+
+/// Returns whether text between two delimiters contains a blank line, ending a paragraph.
+fn is_blank_line_between(text: &str) -> bool {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    lines.len() > 2
+        && lines[1..lines.len() - 1]
+            .iter()
+            .any(|line| line.trim().is_empty())
+}
+
+/// An error in TeX math.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MathError(String);
+
+impl Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+type MathResult<T> = Result<T, MathError>;
+
+macro_rules! math_error {
+    ($($arg:tt)*) => {
+        Err(MathError(format!($($arg)*)))
+    };
+}
+
+/// Converts TeX math to a MathML `math` element.
+pub(crate) fn tex_to_mathml(tex: &str, display: bool) -> MathResult<String> {
+    let mut parser = TexParser {
+        tex,
+        pos: 0,
+        display,
+        variant: None,
+    };
+
+    let row = parser.parse_row(&[])?;
+
+    let mut mathml = String::from(r#"<math xmlns="http://www.w3.org/1998/Math/MathML""#);
+    if display {
+        mathml.push_str(r#" display="block""#);
+    }
+    mathml.push('>');
+    push_row(&mut mathml, &row);
+    mathml.push_str("</math>");
+
+    Ok(mathml)
+}
+
+/// Appends text content to HTML, encoding ASCII punctuation as character references so that it
+/// is not interpreted as markdown.
+fn push_text(html: &mut String, text: &str) {
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            write!(html, "&#{};", c as u32).unwrap();
+        } else {
+            html.push(c);
+        }
+    }
+}
+
+/// Appends a row of elements, wrapping them in an `mrow` if there is not exactly one.
+fn push_row(mathml: &mut String, row: &[Atom]) {
+    match row {
+        [atom] => mathml.push_str(&atom.mathml),
+        _ => {
+            mathml.push_str("<mrow>");
+            for atom in row {
+                mathml.push_str(&atom.mathml);
+            }
+            mathml.push_str("</mrow>");
+        }
+    }
+}
+
+fn element(name: &str, text: &str) -> String {
+    let mut mathml = format!("<{}>", name);
+    push_text(&mut mathml, text);
+    write!(mathml, "</{}>", name).unwrap();
+    mathml
+}
+
+/// A MathML element.
+#[derive(Debug)]
+struct Atom {
+    mathml: String,
+
+    /// Whether scripts are placed under and over the element in display math, such as for `\sum`.
+    limits: bool,
+}
+
+impl Atom {
+    fn new(mathml: String) -> Self {
+        Atom {
+            mathml,
+            limits: false,
+        }
+    }
+
+    fn with_limits(mathml: String) -> Self {
+        Atom {
+            mathml,
+            limits: true,
+        }
+    }
+}
+
+/// A token that ends a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Terminator {
+    Brace,
+    Bracket,
+    Ampersand,
+    NewRow,
+    Right,
+    End,
+}
+
+impl Terminator {
+    fn missing(self) -> MathError {
+        MathError(String::from(match self {
+            Terminator::Brace => "missing }",
+            Terminator::Bracket => "missing ]",
+            Terminator::Right => r"missing \right",
+            Terminator::Ampersand | Terminator::NewRow | Terminator::End => r"missing \end",
+        }))
+    }
+}
+
+/// A font style applied with a command such as `\mathbf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    Normal,
+    Bold,
+    Italic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+struct TexParser<'a> {
+    tex: &'a str,
+    pos: usize,
+    display: bool,
+    variant: Option<Variant>,
+}
+
+impl<'a> TexParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.tex[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Returns the name of the command at the current position, without consuming it.
+    fn peek_command(&self) -> Option<&'a str> {
+        let rest = self.rest().strip_prefix('\\')?;
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+
+        match len {
+            0 => rest.chars().next().map(|c| &rest[..c.len_utf8()]),
+            _ => Some(&rest[..len]),
+        }
+    }
+
+    fn next_command(&mut self) -> Option<&'a str> {
+        let name = self.peek_command()?;
+        self.pos += 1 + name.len();
+        Some(name)
+    }
+
+    fn peek_terminator(&self) -> Option<Terminator> {
+        match self.peek()? {
+            '}' => Some(Terminator::Brace),
+            ']' => Some(Terminator::Bracket),
+            '&' => Some(Terminator::Ampersand),
+            '\\' => match self.peek_command()? {
+                "\\" => Some(Terminator::NewRow),
+                "right" => Some(Terminator::Right),
+                "end" => Some(Terminator::End),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses elements until the end of the TeX or one of the terminators, which is not consumed.
+    fn parse_row(&mut self, terminators: &[Terminator]) -> MathResult<Vec<Atom>> {
+        let mut row = vec![];
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek().is_none() {
+                return match terminators.first() {
+                    Some(terminator) => Err(terminator.missing()),
+                    None => Ok(row),
+                };
+            }
+
+            match self.peek_terminator() {
+                Some(terminator) if terminators.contains(&terminator) => return Ok(row),
+                Some(Terminator::Brace) => return math_error!("unexpected }}"),
+                Some(Terminator::Ampersand) => return math_error!("unexpected &"),
+                Some(Terminator::NewRow) => {
+                    return math_error!(r"line breaks are only allowed in environments")
+                }
+                Some(Terminator::Right) => return math_error!(r"\right without \left"),
+                Some(Terminator::End) => return math_error!(r"\end without \begin"),
+                Some(Terminator::Bracket) | None => {}
+            }
+
+            let base = match self.peek() {
+                Some('^' | '_') => Atom::new(String::from("<mrow></mrow>")),
+                _ => self.parse_atom(false)?,
+            };
+
+            row.push(self.parse_scripts(base)?);
+        }
+    }
+
+    /// Parses any superscript and subscript following `base`.
+    fn parse_scripts(&mut self, base: Atom) -> MathResult<Atom> {
+        let mut sub = None;
+        let mut sup = None;
+
+        loop {
+            self.skip_whitespace();
+
+            let script = match self.peek() {
+                Some('^') => &mut sup,
+                Some('_') => &mut sub,
+                _ => break,
+            };
+
+            let c = self.next_char().unwrap();
+            if script.is_some() {
+                return math_error!(
+                    "double {}",
+                    if c == '^' { "superscript" } else { "subscript" }
+                );
+            }
+
+            *script = Some(self.parse_argument()?);
+        }
+
+        let under_over = base.limits && self.display;
+        let (sub_name, sup_name, both_name) = if under_over {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+
+        let mathml = match (sub, sup) {
+            (None, None) => return Ok(base),
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", sub_name, base.mathml, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", sup_name, base.mathml, sup),
+            (Some(sub), Some(sup)) => {
+                format!("<{0}>{1}{2}{3}</{0}>", both_name, base.mathml, sub, sup)
+            }
+        };
+
+        Ok(Atom::new(mathml))
+    }
+
+    /// Parses a command argument: a group in braces, or a single token.
+    fn parse_argument(&mut self) -> MathResult<String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            None => math_error!("missing argument"),
+            Some('{') => {
+                self.pos += 1;
+                let row = self.parse_row(&[Terminator::Brace])?;
+                self.pos += 1;
+
+                let mut mathml = String::new();
+                push_row(&mut mathml, &row);
+                Ok(mathml)
+            }
+            Some(_) => Ok(self.parse_atom(true)?.mathml),
+        }
+    }
+
+    /// Parses the raw text of an argument in braces.
+    fn parse_text_argument(&mut self) -> MathResult<&'a str> {
+        self.skip_whitespace();
+
+        if self.next_char() != Some('{') {
+            return math_error!("expected {{");
+        }
+
+        let rest = self.rest();
+        let mut depth = 0;
+        let len = rest
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' if depth == 0 => return Some(i),
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                None
+            })
+            .ok_or_else(|| Terminator::Brace.missing())?;
+
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+
+    /// Parses a single element. If `single` is true, only a single digit of a number is parsed.
+    fn parse_atom(&mut self, single: bool) -> MathResult<Atom> {
+        self.skip_whitespace();
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return math_error!("missing argument"),
+        };
+
+        if c == '\\' {
+            return self.parse_command();
+        }
+
+        self.next_char();
+
+        let atom = match c {
+            '{' => {
+                let row = self.parse_row(&[Terminator::Brace])?;
+                self.pos += 1;
+                let mut mathml = String::from("<mrow>");
+                for atom in row {
+                    mathml.push_str(&atom.mathml);
+                }
+                mathml.push_str("</mrow>");
+                Atom::new(mathml)
+            }
+            '}' => return math_error!("unexpected }}"),
+            '&' => return math_error!("unexpected &"),
+            '#' | '%' => return math_error!("unexpected {}", c),
+            '0'..='9' => {
+                let mut number = String::from(c);
+                if !single {
+                    let rest = self.rest();
+                    let len = rest
+                        .char_indices()
+                        .find(|&(i, c)| {
+                            !(c.is_ascii_digit()
+                                || c == '.'
+                                    && rest[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+                        })
+                        .map_or(rest.len(), |(i, _)| i);
+                    number.push_str(&rest[..len]);
+                    self.pos += len;
+                }
+                let number = number.chars().map(|c| self.styled(c)).collect::<String>();
+                Atom::new(element("mn", &number))
+            }
+            '-' => Atom::new(element("mo", "\u{2212}")),
+            '\'' => Atom::new(element("mo", "\u{2032}")),
+            '~' => Atom::new(space("0.3333em")),
+            c if c.is_alphabetic() => self.identifier(c),
+            c => Atom::new(element("mo", &c.to_string())),
+        };
+
+        Ok(atom)
+    }
+
+    fn identifier(&self, c: char) -> Atom {
+        match self.variant {
+            Some(Variant::Normal) => Atom::new(format!(
+                r#"<mi mathvariant="normal">{}</mi>"#,
+                c.encode_utf8(&mut [0; 4])
+            )),
+            _ => Atom::new(element("mi", &self.styled(c).to_string())),
+        }
+    }
+
+    /// Applies the current font style to an ASCII letter or digit.
+    fn styled(&self, c: char) -> char {
+        styled(c, self.variant)
+    }
+
+    fn parse_command(&mut self) -> MathResult<Atom> {
+        let name = match self.next_command() {
+            Some(name) => name,
+            None => return math_error!("missing command name"),
+        };
+
+        let atom = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                Atom::new(format!("<mfrac>{}{}</mfrac>", numerator, denominator))
+            }
+            "binom" => {
+                let n = self.parse_argument()?;
+                let k = self.parse_argument()?;
+                Atom::new(format!(
+                    r#"<mrow><mo>&#40;</mo><mfrac linethickness="0">{}{}</mfrac><mo>&#41;</mo></mrow>"#,
+                    n, k
+                ))
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let index = self.parse_row(&[Terminator::Bracket])?;
+                    self.pos += 1;
+                    let radicand = self.parse_argument()?;
+
+                    let mut mathml = format!("<mroot>{}", radicand);
+                    push_row(&mut mathml, &index);
+                    mathml.push_str("</mroot>");
+                    Atom::new(mathml)
+                } else {
+                    Atom::new(format!("<msqrt>{}</msqrt>", self.parse_argument()?))
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" => {
+                Atom::new(element("mtext", self.parse_text_argument()?))
+            }
+            "operatorname" => {
+                // The starred form places scripts under and over the name, like `\lim`.
+                let limits = self.rest().starts_with('*');
+                if limits {
+                    self.pos += 1;
+                }
+
+                let name = element("mi", self.parse_text_argument()?);
+                if limits {
+                    Atom::with_limits(name)
+                } else {
+                    Atom::new(name)
+                }
+            }
+            "mathrm" | "mathbf" | "mathit" | "mathbb" | "mathcal" | "mathscr" | "mathfrak"
+            | "mathsf" | "mathtt" | "boldsymbol" | "bm" => {
+                let variant = match name {
+                    "mathrm" => Variant::Normal,
+                    "mathbf" | "boldsymbol" | "bm" => Variant::Bold,
+                    "mathit" => Variant::Italic,
+                    "mathbb" => Variant::DoubleStruck,
+                    "mathcal" | "mathscr" => Variant::Script,
+                    "mathfrak" => Variant::Fraktur,
+                    "mathsf" => Variant::SansSerif,
+                    _ => Variant::Monospace,
+                };
+
+                let outer = self.variant.replace(variant);
+                let argument = self.parse_argument();
+                self.variant = outer;
+                Atom::new(argument?)
+            }
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let row = self.parse_row(&[Terminator::Right])?;
+                self.next_command();
+                let close = self.parse_delimiter()?;
+
+                let mut mathml = String::from("<mrow>");
+                mathml.push_str(&open);
+                for atom in row {
+                    mathml.push_str(&atom.mathml);
+                }
+                mathml.push_str(&close);
+                mathml.push_str("</mrow>");
+                Atom::new(mathml)
+            }
+            "begin" => self.parse_environment()?,
+            "," | ">" => Atom::new(space("0.1667em")),
+            ":" => Atom::new(space("0.2222em")),
+            ";" => Atom::new(space("0.2778em")),
+            "!" => Atom::new(space("-0.1667em")),
+            " " => Atom::new(space("0.3333em")),
+            "quad" => Atom::new(space("1em")),
+            "qquad" => Atom::new(space("2em")),
+            "{" | "}" | "$" | "%" | "&" | "#" | "_" => Atom::new(element("mo", name)),
+            "|" => Atom::new(element("mo", "\u{2016}")),
+            name => {
+                if let Some(accent) = accent(name) {
+                    let base = self.parse_argument()?;
+                    return Ok(Atom::new(match name {
+                        "underline" => format!(
+                            r#"<munder accentunder="true">{}<mo>{}</mo></munder>"#,
+                            base, accent
+                        ),
+                        _ => format!(
+                            r#"<mover accent="true">{}<mo>{}</mo></mover>"#,
+                            base, accent
+                        ),
+                    }));
+                }
+
+                match symbol(name) {
+                    Some(Symbol::Identifier(c)) => Atom::new(element("mi", c)),
+                    Some(Symbol::Operator(c)) => Atom::new(element("mo", c)),
+                    Some(Symbol::LargeOperator(c)) => Atom::with_limits(element("mo", c)),
+                    Some(Symbol::Integral(c)) => Atom::new(element("mo", c)),
+                    Some(Symbol::Function) => Atom::new(element("mi", name)),
+                    Some(Symbol::FunctionWithLimits) => Atom::with_limits(element("mi", name)),
+                    None => return math_error!(r"unknown command \{}", name),
+                }
+            }
+        };
+
+        Ok(atom)
+    }
+
+    /// Parses the delimiter following `\left` or `\right`.
+    fn parse_delimiter(&mut self) -> MathResult<String> {
+        self.skip_whitespace();
+
+        let delimiter = match self.peek() {
+            Some('\\') => match self.next_command() {
+                Some("{") => "{",
+                Some("}") => "}",
+                Some("|") => "\u{2016}",
+                Some(name) => match symbol(name) {
+                    Some(Symbol::Operator(c)) => c,
+                    _ => return math_error!(r"invalid delimiter \{}", name),
+                },
+                None => return math_error!("missing delimiter"),
+            },
+            Some('.') => {
+                self.pos += 1;
+                return Ok(String::new());
+            }
+            Some(c @ ('(' | ')' | '[' | ']' | '|' | '/' | '<' | '>')) => {
+                self.pos += 1;
+                match c {
+                    '<' => "\u{27E8}",
+                    '>' => "\u{27E9}",
+                    _ => &self.tex[self.pos - 1..self.pos],
+                }
+            }
+            _ => return math_error!("missing delimiter"),
+        };
+
+        let mut mathml = String::from(r#"<mo fence="true" stretchy="true">"#);
+        push_text(&mut mathml, delimiter);
+        mathml.push_str("</mo>");
+        Ok(mathml)
+    }
+
+    /// Parses an environment after `\begin`, such as `matrix` or `cases`.
+    fn parse_environment(&mut self) -> MathResult<Atom> {
+        let name = self.parse_text_argument()?;
+
+        let (open, close, align) = match name {
+            "matrix" | "gathered" => ("", "", None),
+            "aligned" | "align" | "align*" => ("", "", Some("right left")),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("\u{2016}", "\u{2016}", None),
+            "cases" => ("{", "", Some("left")),
+            _ => return math_error!("unknown environment {}", name),
+        };
+
+        let mut rows = vec![];
+        let mut cells = vec![];
+
+        loop {
+            let row =
+                self.parse_row(&[Terminator::Ampersand, Terminator::NewRow, Terminator::End])?;
+            cells.push(row);
+
+            match self.peek_terminator() {
+                Some(Terminator::Ampersand) => self.pos += 1,
+                Some(Terminator::NewRow) => {
+                    self.pos += 2;
+                    rows.push(std::mem::take(&mut cells));
+                }
+                _ => break,
+            }
+        }
+
+        // A trailing line break does not start a new row.
+        if !(cells.len() == 1 && cells[0].is_empty()) || rows.is_empty() {
+            rows.push(cells);
+        }
+
+        self.next_command();
+        let end = self.parse_text_argument()?;
+        if end != name {
+            return math_error!(r"expected \end{{{}}}, found \end{{{}}}", name, end);
+        }
+
+        let mut mathml = String::from("<mrow>");
+        if !open.is_empty() {
+            mathml.push_str(r#"<mo fence="true" stretchy="true">"#);
+            push_text(&mut mathml, open);
+            mathml.push_str("</mo>");
+        }
+
+        match align {
+            Some(align) => write!(mathml, r#"<mtable columnalign="{}">"#, align).unwrap(),
+            None => mathml.push_str("<mtable>"),
+        }
+        for row in rows {
+            mathml.push_str("<mtr>");
+            for cell in row {
+                mathml.push_str("<mtd>");
+                push_row(&mut mathml, &cell);
+                mathml.push_str("</mtd>");
+            }
+            mathml.push_str("</mtr>");
+        }
+        mathml.push_str("</mtable>");
+
+        if !close.is_empty() {
+            mathml.push_str(r#"<mo fence="true" stretchy="true">"#);
+            push_text(&mut mathml, close);
+            mathml.push_str("</mo>");
+        }
+        mathml.push_str("</mrow>");
+
+        Ok(Atom::new(mathml))
+    }
+}
+
+fn space(width: &str) -> String {
+    format!(r#"<mspace width="{}"/>"#, width)
+}
+
+/// Returns the character placed over (or under) the argument of an accent command.
+fn accent(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "hat" | "widehat" => "^",
+        "bar" | "overline" => "\u{203E}",
+        "underline" => "_",
+        "vec" | "overrightarrow" => "\u{2192}",
+        "overleftarrow" => "\u{2190}",
+        "dot" => "\u{02D9}",
+        "ddot" => "\u{00A8}",
+        "tilde" | "widetilde" => "~",
+        "check" => "\u{02C7}",
+        "breve" => "\u{02D8}",
+        "acute" => "\u{00B4}",
+        "grave" => "\u{0060}",
+        _ => return None,
+    })
+}
+
+enum Symbol {
+    Identifier(&'static str),
+    Operator(&'static str),
+    LargeOperator(&'static str),
+    Integral(&'static str),
+    Function,
+    FunctionWithLimits,
+}
+
+fn symbol(name: &str) -> Option<Symbol> {
+    use self::Symbol::*;
+
+    Some(match name {
+        "alpha" => Identifier("\u{03B1}"),
+        "beta" => Identifier("\u{03B2}"),
+        "gamma" => Identifier("\u{03B3}"),
+        "delta" => Identifier("\u{03B4}"),
+        "epsilon" => Identifier("\u{03F5}"),
+        "varepsilon" => Identifier("\u{03B5}"),
+        "zeta" => Identifier("\u{03B6}"),
+        "eta" => Identifier("\u{03B7}"),
+        "theta" => Identifier("\u{03B8}"),
+        "vartheta" => Identifier("\u{03D1}"),
+        "iota" => Identifier("\u{03B9}"),
+        "kappa" => Identifier("\u{03BA}"),
+        "lambda" => Identifier("\u{03BB}"),
+        "mu" => Identifier("\u{03BC}"),
+        "nu" => Identifier("\u{03BD}"),
+        "xi" => Identifier("\u{03BE}"),
+        "pi" => Identifier("\u{03C0}"),
+        "varpi" => Identifier("\u{03D6}"),
+        "rho" => Identifier("\u{03C1}"),
+        "varrho" => Identifier("\u{03F1}"),
+        "sigma" => Identifier("\u{03C3}"),
+        "varsigma" => Identifier("\u{03C2}"),
+        "tau" => Identifier("\u{03C4}"),
+        "upsilon" => Identifier("\u{03C5}"),
+        "phi" => Identifier("\u{03D5}"),
+        "varphi" => Identifier("\u{03C6}"),
+        "chi" => Identifier("\u{03C7}"),
+        "psi" => Identifier("\u{03C8}"),
+        "omega" => Identifier("\u{03C9}"),
+        "Gamma" => Identifier("\u{0393}"),
+        "Delta" => Identifier("\u{0394}"),
+        "Theta" => Identifier("\u{0398}"),
+        "Lambda" => Identifier("\u{039B}"),
+        "Xi" => Identifier("\u{039E}"),
+        "Pi" => Identifier("\u{03A0}"),
+        "Sigma" => Identifier("\u{03A3}"),
+        "Upsilon" => Identifier("\u{03A5}"),
+        "Phi" => Identifier("\u{03A6}"),
+        "Psi" => Identifier("\u{03A8}"),
+        "Omega" => Identifier("\u{03A9}"),
+        "infty" => Identifier("\u{221E}"),
+        "partial" => Identifier("\u{2202}"),
+        "nabla" => Identifier("\u{2207}"),
+        "emptyset" | "varnothing" => Identifier("\u{2205}"),
+        "hbar" => Identifier("\u{210F}"),
+        "ell" => Identifier("\u{2113}"),
+        "aleph" => Identifier("\u{2135}"),
+        "Re" => Identifier("\u{211C}"),
+        "Im" => Identifier("\u{2111}"),
+        "times" => Operator("\u{00D7}"),
+        "cdot" => Operator("\u{22C5}"),
+        "ast" => Operator("\u{2217}"),
+        "star" => Operator("\u{22C6}"),
+        "circ" => Operator("\u{2218}"),
+        "bullet" => Operator("\u{2219}"),
+        "div" => Operator("\u{00F7}"),
+        "pm" => Operator("\u{00B1}"),
+        "mp" => Operator("\u{2213}"),
+        "oplus" => Operator("\u{2295}"),
+        "otimes" => Operator("\u{2297}"),
+        "le" | "leq" => Operator("\u{2264}"),
+        "ge" | "geq" => Operator("\u{2265}"),
+        "ll" => Operator("\u{226A}"),
+        "gg" => Operator("\u{226B}"),
+        "ne" | "neq" => Operator("\u{2260}"),
+        "approx" => Operator("\u{2248}"),
+        "equiv" => Operator("\u{2261}"),
+        "sim" => Operator("\u{223C}"),
+        "simeq" => Operator("\u{2243}"),
+        "cong" => Operator("\u{2245}"),
+        "propto" => Operator("\u{221D}"),
+        "to" | "rightarrow" => Operator("\u{2192}"),
+        "leftarrow" | "gets" => Operator("\u{2190}"),
+        "leftrightarrow" => Operator("\u{2194}"),
+        "Rightarrow" | "implies" => Operator("\u{21D2}"),
+        "Leftarrow" => Operator("\u{21D0}"),
+        "Leftrightarrow" | "iff" => Operator("\u{21D4}"),
+        "mapsto" => Operator("\u{21A6}"),
+        "in" => Operator("\u{2208}"),
+        "notin" => Operator("\u{2209}"),
+        "ni" => Operator("\u{220B}"),
+        "subset" => Operator("\u{2282}"),
+        "subseteq" => Operator("\u{2286}"),
+        "supset" => Operator("\u{2283}"),
+        "supseteq" => Operator("\u{2287}"),
+        "cup" => Operator("\u{222A}"),
+        "cap" => Operator("\u{2229}"),
+        "setminus" => Operator("\u{2216}"),
+        "forall" => Operator("\u{2200}"),
+        "exists" => Operator("\u{2203}"),
+        "neg" | "lnot" => Operator("\u{00AC}"),
+        "land" | "wedge" => Operator("\u{2227}"),
+        "lor" | "vee" => Operator("\u{2228}"),
+        "perp" => Operator("\u{22A5}"),
+        "parallel" => Operator("\u{2225}"),
+        "mid" => Operator("\u{2223}"),
+        "ldots" | "dots" => Operator("\u{2026}"),
+        "cdots" => Operator("\u{22EF}"),
+        "vdots" => Operator("\u{22EE}"),
+        "ddots" => Operator("\u{22F1}"),
+        "prime" => Operator("\u{2032}"),
+        "langle" => Operator("\u{27E8}"),
+        "rangle" => Operator("\u{27E9}"),
+        "lfloor" => Operator("\u{230A}"),
+        "rfloor" => Operator("\u{230B}"),
+        "lceil" => Operator("\u{2308}"),
+        "rceil" => Operator("\u{2309}"),
+        "lvert" | "rvert" | "vert" => Operator("|"),
+        "lVert" | "rVert" | "Vert" => Operator("\u{2016}"),
+        "sum" => LargeOperator("\u{2211}"),
+        "prod" => LargeOperator("\u{220F}"),
+        "coprod" => LargeOperator("\u{2210}"),
+        "bigcup" => LargeOperator("\u{22C3}"),
+        "bigcap" => LargeOperator("\u{22C2}"),
+        "bigoplus" => LargeOperator("\u{2A01}"),
+        "bigotimes" => LargeOperator("\u{2A02}"),
+        "int" => Integral("\u{222B}"),
+        "iint" => Integral("\u{222C}"),
+        "iiint" => Integral("\u{222D}"),
+        "oint" => Integral("\u{222E}"),
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan" | "sinh"
+        | "cosh" | "tanh" | "coth" | "log" | "ln" | "lg" | "exp" | "arg" | "deg" | "dim"
+        | "hom" | "ker" => Function,
+        "lim" | "liminf" | "limsup" | "max" | "min" | "sup" | "inf" | "det" | "gcd" | "Pr" => {
+            FunctionWithLimits
+        }
+        _ => return None,
+    })
+}
+
+/// Converts an ASCII letter or digit to the Unicode mathematical alphanumeric symbol for a font
+/// style.
+fn styled(c: char, variant: Option<Variant>) -> char {
+    let variant = match variant {
+        Some(variant) => variant,
+        None => return c,
+    };
+
+    // Letters that were encoded before the mathematical alphanumeric symbols block, and are
+    // reserved within it.
+    let exception = match (variant, c) {
+        (Variant::Italic, 'h') => Some('\u{210E}'),
+        (Variant::DoubleStruck, 'C') => Some('\u{2102}'),
+        (Variant::DoubleStruck, 'H') => Some('\u{210D}'),
+        (Variant::DoubleStruck, 'N') => Some('\u{2115}'),
+        (Variant::DoubleStruck, 'P') => Some('\u{2119}'),
+        (Variant::DoubleStruck, 'Q') => Some('\u{211A}'),
+        (Variant::DoubleStruck, 'R') => Some('\u{211D}'),
+        (Variant::DoubleStruck, 'Z') => Some('\u{2124}'),
+        (Variant::Script, 'B') => Some('\u{212C}'),
+        (Variant::Script, 'E') => Some('\u{2130}'),
+        (Variant::Script, 'F') => Some('\u{2131}'),
+        (Variant::Script, 'H') => Some('\u{210B}'),
+        (Variant::Script, 'I') => Some('\u{2110}'),
+        (Variant::Script, 'L') => Some('\u{2112}'),
+        (Variant::Script, 'M') => Some('\u{2133}'),
+        (Variant::Script, 'R') => Some('\u{211B}'),
+        (Variant::Script, 'e') => Some('\u{212F}'),
+        (Variant::Script, 'g') => Some('\u{210A}'),
+        (Variant::Script, 'o') => Some('\u{2134}'),
+        (Variant::Fraktur, 'C') => Some('\u{212D}'),
+        (Variant::Fraktur, 'H') => Some('\u{210C}'),
+        (Variant::Fraktur, 'I') => Some('\u{2111}'),
+        (Variant::Fraktur, 'R') => Some('\u{211C}'),
+        (Variant::Fraktur, 'Z') => Some('\u{2128}'),
+        _ => None,
+    };
+
+    if let Some(c) = exception {
+        return c;
+    }
+
+    let (upper, digits) = match variant {
+        Variant::Normal => return c,
+        Variant::Bold => (0x1D400, Some(0x1D7CE)),
+        Variant::Italic => (0x1D434, None),
+        Variant::Script => (0x1D49C, None),
+        Variant::Fraktur => (0x1D504, None),
+        Variant::DoubleStruck => (0x1D538, Some(0x1D7D8)),
+        Variant::SansSerif => (0x1D5A0, Some(0x1D7E2)),
+        Variant::Monospace => (0x1D670, Some(0x1D7F6)),
+    };
+
+    let code_point = match c {
+        'A'..='Z' => upper + (c as u32 - 'A' as u32),
+        'a'..='z' => upper + 26 + (c as u32 - 'a' as u32),
+        '0'..='9' => match digits {
+            Some(digits) => digits + (c as u32 - '0' as u32),
+            None => return c,
+        },
+        _ => return c,
+    };
+
+    char::from_u32(code_point).unwrap_or(c)
+}
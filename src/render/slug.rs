@@ -0,0 +1,41 @@
+//! GitHub-compatible ids for headings.
+
+use std::collections::HashMap;
+
+/// Generates unique heading ids with the same algorithm as GitHub.
+///
+/// The text of the heading is lowercased, characters other than letters, numbers, spaces, `-` and
+/// `_` are removed, and spaces are replaced with `-`. Repeated ids are suffixed with `-1`, `-2`,
+/// and so on.
+#[derive(Debug, Default)]
+pub(crate) struct Slugger {
+    /// The number of times that each id has been repeated.
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    /// Returns a unique id for a heading with the given text.
+    pub(crate) fn slug(&mut self, text: &str) -> String {
+        let slug = text
+            .to_lowercase()
+            .chars()
+            .filter(|&c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+            .map(|c| if c == ' ' { '-' } else { c })
+            .collect::<String>();
+
+        let mut id = slug.clone();
+        while self.occurrences.contains_key(&id) {
+            let count = self.occurrences.get_mut(&slug).unwrap();
+            *count += 1;
+            id = format!("{}-{}", slug, count);
+        }
+
+        self.occurrences.insert(id.clone(), 0);
+        id
+    }
+
+    /// Reserves an id that was set explicitly, so that generated ids do not repeat it.
+    pub(crate) fn reserve(&mut self, id: &str) {
+        self.occurrences.entry(id.to_owned()).or_insert(0);
+    }
+}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use axum::{
     body::Body,
@@ -9,30 +11,66 @@ use axum::{
         Extension,
     },
     http::{header, HeaderMap, Request, StatusCode, Uri},
-    response::{Html, IntoResponse},
+    response::{Html, IntoResponse, Response},
 };
 use handlebars::Handlebars;
 use include_dir::{include_dir, Dir};
 use serde::Serialize;
 use serde::Serializer;
-use tokio::sync::watch::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
 use tower::util::ServiceExt;
 use tower_http::services::ServeDir;
 use tracing::log::*;
 
-use crate::Config;
+use crate::diff;
+use crate::document::{Documents, Subscription};
+use crate::protocol::{ClientMessage, ControlMessage, Protocol, ServerMessage, SUBPROTOCOLS};
+use crate::{Config, Metadata};
 
 const STATIC_FILES: Dir = include_dir!("$CARGO_MANIFEST_DIR/static");
 
+/// The release of KaTeX that is vendored by `scripts/vendor.sh`.
+const KATEX_VERSION: &str = "0.16.9";
+
+/// The release of mermaid that is vendored by `scripts/vendor.sh`.
+const MERMAID_VERSION: &str = "10.9.1";
+
+/// Returns the URL of the `dist` directory of a library that is vendored by `scripts/vendor.sh`.
+///
+/// If the library was not vendored when the server was built, the same release is loaded from
+/// jsDelivr instead, so that the page does not request files that the server cannot serve.
+fn vendor_dist_url(package: &str, version: &str, file: &str) -> String {
+    if STATIC_FILES
+        .get_file(format!("vendor/{}/dist/{}", package, file))
+        .is_some()
+    {
+        format!("/__/vendor/{}/dist", package)
+    } else {
+        format!("https://cdn.jsdelivr.net/npm/{}@{}/dist", package, version)
+    }
+}
+
+/// Returns whether a highlight.js theme with the given name is bundled with the server.
+pub(crate) fn highlight_theme_exists(theme: &str) -> bool {
+    STATIC_FILES
+        .get_file(format!(
+            "vendor/highlight.js/build/styles/{}.min.css",
+            theme
+        ))
+        .is_some()
+}
+
 pub(crate) async fn serve_asset(extract::Path(path): extract::Path<PathBuf>) -> impl IntoResponse {
     let path = path.strip_prefix("/").unwrap_or(&path);
 
-    let file = match STATIC_FILES.get_file(&path) {
+    let file = match STATIC_FILES.get_file(path) {
         Some(file) => file,
         None => return Err((StatusCode::NOT_FOUND, "file not found")),
     };
 
-    let mime = mime_guess::from_path(&path);
+    let mime = mime_guess::from_path(path);
 
     let mut headers = HeaderMap::new();
     headers.insert(
@@ -43,13 +81,32 @@ pub(crate) async fn serve_asset(extract::Path(path): extract::Path<PathBuf>) ->
     Ok((headers, file.contents()))
 }
 
-pub(crate) async fn websocket_handler(
+/// Serves the document at the request path, or a static file if there is no such document.
+pub(crate) async fn document_handler(
     ws: Option<WebSocketUpgrade>,
     Extension(config): Extension<Arc<RwLock<Config>>>,
-    Extension(html_rx): Extension<Receiver<String>>,
-) -> impl IntoResponse {
+    Extension(documents): Extension<Arc<Documents>>,
+    Extension(clients): Extension<Arc<Clients>>,
+    Extension(closed): Extension<CancellationToken>,
+    req: Request<Body>,
+) -> Response {
+    let name = req.uri().path().trim_start_matches('/');
+
+    let subscription = match documents.get(name) {
+        Some(document) => document.subscribe(),
+        None => return serve_static_file(config, req).await.into_response(),
+    };
+
+    let metadata = subscription.preview_rx.borrow().metadata.clone();
+
     if let Some(ws) = ws {
-        ws.on_upgrade(|ws| async { handle_websocket(ws, html_rx).await })
+        let raw_html_fallback = config.read().unwrap().raw_html_fallback;
+
+        ws.protocols(SUBPROTOCOLS).on_upgrade(move |ws| async move {
+            let _client = clients.connect();
+            let protocol = Protocol::negotiate(ws.protocol(), raw_html_fallback);
+            handle_websocket(ws, protocol, subscription, closed, clients.close_timeout).await
+        })
     } else {
         let config = config.read().unwrap();
 
@@ -60,6 +117,17 @@ pub(crate) async fn websocket_handler(
                     remote_custom_css: &config.css_links,
                     local_custom_css: &config.custom_styles,
                     highlight_theme: &config.highlight_theme,
+                    math_delimiters: config
+                        .math
+                        .then(|| serde_json::to_string(&config.math_delimiters).unwrap()),
+                    katex_url: vendor_dist_url("katex", KATEX_VERSION, "katex.min.js"),
+                    mermaid_url: config
+                        .mermaid
+                        .then(|| vendor_dist_url("mermaid", MERMAID_VERSION, "mermaid.min.js")),
+                    toc: config.toc,
+                    documents: &documents.names(),
+                    title: metadata.as_ref().and_then(Metadata::title),
+                    metadata: metadata.as_ref(),
                 },
             )
             .unwrap();
@@ -68,20 +136,210 @@ pub(crate) async fn websocket_handler(
     }
 }
 
-async fn handle_websocket(mut socket: WebSocket, mut html_rx: Receiver<String>) {
-    while html_rx.changed().await.is_ok() {
-        let html = html_rx.borrow().clone();
-        info!("received new html: {}", html);
-        socket.send(AxumMessage::Text(html)).await.unwrap();
+async fn handle_websocket(
+    mut socket: WebSocket,
+    protocol: Protocol,
+    subscription: Subscription,
+    closed: CancellationToken,
+    close_timeout: Duration,
+) {
+    let Subscription {
+        mut preview_rx,
+        mut scroll_rx,
+        events_tx,
+    } = subscription;
+    let mut revision = 0;
+
+    // The top-level elements of the HTML last sent to the client, if it could be split.
+    let mut sent_blocks: Option<Vec<String>> = None;
+    let mut sent_metadata: Option<Metadata> = None;
+    let mut sent_error = false;
+
+    'outer: loop {
+        let messages = {
+            let preview = preview_rx.borrow_and_update();
+            let mut messages = vec![];
+            let blocks;
+
+            if preview.revision != revision {
+                info!("received new html: {}", preview.html);
+                revision = preview.revision;
+                blocks = diff::split_blocks(&preview.html);
+
+                match (protocol, &sent_blocks, &blocks) {
+                    (Protocol::V1, Some(old), Some(new)) => {
+                        let patch = diff::diff(old, new);
+                        if !patch.is_empty() {
+                            messages.push(ServerMessage::Patch {
+                                start: patch.start,
+                                delete: patch.delete,
+                                insert: patch.insert,
+                                blocks: new.len(),
+                            });
+                        }
+                    }
+                    _ => messages.push(ServerMessage::Html {
+                        html: &preview.html,
+                    }),
+                }
+
+                sent_blocks = blocks
+                    .as_ref()
+                    .map(|blocks| blocks.iter().map(|&block| block.to_owned()).collect());
+
+                if preview.metadata != sent_metadata {
+                    messages.push(ServerMessage::Metadata {
+                        metadata: preview.metadata.as_ref(),
+                    });
+                    sent_metadata = preview.metadata.clone();
+                }
+            }
+
+            match &preview.error {
+                Some(error) => messages.push(ServerMessage::Error { message: error }),
+                None if sent_error => messages.push(ServerMessage::ErrorCleared),
+                None => {}
+            }
+            sent_error = preview.error.is_some();
+
+            messages
+                .iter()
+                .filter_map(|message| protocol.encode(message))
+                .collect::<Vec<_>>()
+        };
+
+        for message in messages {
+            if socket.send(AxumMessage::Text(message)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                res = preview_rx.changed() => match res {
+                    Ok(()) => break,
+                    Err(_) => break 'outer,
+                },
+                res = scroll_rx.recv() => match res {
+                    Ok(line) => {
+                        if let Some(message) = protocol.encode(&ServerMessage::Scroll { line }) {
+                            if socket.send(AxumMessage::Text(message)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    // Skipped scroll positions have already been superseded.
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break 'outer,
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(AxumMessage::Text(text))) => {
+                        match serde_json::from_str::<ClientMessage>(&text) {
+                            Ok(ClientMessage::Event(event)) => {
+                                // Sending only fails if no one is listening for events.
+                                let _ = events_tx.send(event);
+                            }
+                            Ok(ClientMessage::Control(ControlMessage::Resync)) => {
+                                // Send the full HTML of the current revision again.
+                                debug!("client requested resync");
+                                revision = 0;
+                                sent_blocks = None;
+                                break;
+                            }
+                            Err(e) => debug!("ignoring invalid client message: {}", e),
+                        }
+                    }
+                    Some(Ok(AxumMessage::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+                _ = closed.cancelled() => break 'outer,
+            }
+        }
+    }
+
+    // Stop reporting events, so that the event stream ends without waiting for the client.
+    drop(events_tx);
+
+    if socket.send(AxumMessage::Close(None)).await.is_err() {
+        return;
+    }
+
+    // The client remains connected until it completes the closing handshake, or disconnects.
+    let acknowledged = async {
+        while let Some(Ok(message)) = socket.recv().await {
+            if let AxumMessage::Close(_) = message {
+                break;
+            }
+        }
+    };
+
+    if tokio::time::timeout(close_timeout, acknowledged)
+        .await
+        .is_err()
+    {
+        debug!(
+            "client did not acknowledge close within {:?}",
+            close_timeout
+        );
+    }
+}
+
+/// Tracks the number of connected websocket clients.
+#[derive(Debug)]
+pub(crate) struct Clients {
+    count: AtomicUsize,
+    disconnected: Notify,
+
+    /// How long to wait for a client to complete the closing handshake.
+    close_timeout: Duration,
+}
+
+impl Clients {
+    pub(crate) fn new(close_timeout: Duration) -> Self {
+        Clients {
+            count: AtomicUsize::new(0),
+            disconnected: Notify::new(),
+            close_timeout,
+        }
     }
 
-    let _ = socket.send(AxumMessage::Close(None)).await;
+    /// Registers a connected client. The client is considered disconnected when the returned guard
+    /// is dropped.
+    fn connect(self: &Arc<Self>) -> ClientGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        ClientGuard(Arc::clone(self))
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Waits until all clients have disconnected.
+    pub(crate) async fn wait_for_disconnect(&self) {
+        loop {
+            // Register for notifications before checking the count to avoid missing one.
+            let disconnected = self.disconnected.notified();
+
+            if self.count() == 0 {
+                return;
+            }
+
+            disconnected.await;
+        }
+    }
 }
 
-pub(crate) async fn serve_static_file(
-    Extension(config): Extension<Arc<RwLock<Config>>>,
-    req: Request<Body>,
-) -> impl IntoResponse {
+#[derive(Debug)]
+struct ClientGuard(Arc<Clients>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+        self.0.disconnected.notify_waiters();
+    }
+}
+
+async fn serve_static_file(config: Arc<RwLock<Config>>, req: Request<Body>) -> impl IntoResponse {
     let static_root = config.read().unwrap().static_root.to_owned();
 
     let root = match static_root {
@@ -103,6 +361,21 @@ struct TemplateData<'a> {
     remote_custom_css: &'a [Uri],
     local_custom_css: &'a [String],
     highlight_theme: &'a str,
+
+    /// The math delimiters as JSON, or `None` if math is disabled.
+    math_delimiters: Option<String>,
+
+    /// The URL of the directory containing KaTeX.
+    katex_url: String,
+
+    /// The URL of the directory containing mermaid, or `None` if diagrams are disabled.
+    mermaid_url: Option<String>,
+    toc: bool,
+    documents: &'a [String],
+
+    /// The title from the document's metadata, if it has one.
+    title: Option<&'a str>,
+    metadata: Option<&'a Metadata>,
 }
 
 fn serialize_uris_as_strings<S>(uris: &[Uri], serializer: S) -> Result<S::Ok, S::Error>
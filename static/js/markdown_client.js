@@ -1,7 +1,8 @@
 document.addEventListener('DOMContentLoaded', function() {
-    function syntaxHighlight() {
+    function syntaxHighlight(root) {
         if (hljs !== undefined) {
-            var codeBlocks = document.querySelectorAll('pre code');
+            // Skip code that was already highlighted by the server.
+            var codeBlocks = root.querySelectorAll('pre code:not([data-highlighted])');
             for (var i = 0; i < codeBlocks.length; i++) {
                 var codeBlock = codeBlocks[i];
                 hljs.highlightElement(codeBlock);
@@ -16,35 +17,275 @@ document.addEventListener('DOMContentLoaded', function() {
         }
     }
 
-    function renderMath() {
-      if (typeof renderMathInElement === 'function') {
+    // The delimiters are configured by the server. They are absent if math is disabled.
+    var mathDelimiters = document.getElementById('markdown-preview').dataset.mathDelimiters;
+
+    function renderMath(root) {
+      if (mathDelimiters && typeof renderMathInElement === 'function') {
         renderMathInElement(
-            document.getElementById("markdown-preview"),
+            root,
             {
-                delimiters: [
-                    {left: "$$", right: "$$", display: true},
-                    {left: "\\[", right: "\\]", display: true},
-                    {left: "$", right: "$", display: false},
-                    {left: "\\(", right: "\\)", display: false}
-                ]
+                delimiters: JSON.parse(mathDelimiters)
             }
         );
       }
     }
 
+    // Diagrams are enabled by the server, which marks the preview with `data-mermaid`.
+    var mermaidEnabled = (
+        'mermaid' in document.getElementById('markdown-preview').dataset &&
+        typeof mermaid !== 'undefined');
+
+    if (mermaidEnabled) {
+        mermaid.initialize({ startOnLoad: false });
+    }
+
+    var diagramCount = 0;
+
+    // Replace mermaid code blocks with containers for their diagrams. The code blocks are replaced
+    // immediately so that they are not highlighted, and the diagrams are rendered asynchronously.
+    function renderDiagrams(root) {
+        if (!mermaidEnabled) {
+            return;
+        }
+
+        var codeBlocks = root.querySelectorAll('pre > code.language-mermaid');
+        for (var i = 0; i < codeBlocks.length; i++) {
+            var pre = codeBlocks[i].parentNode;
+
+            var container = document.createElement('div');
+            container.className = 'mermaid-diagram';
+            if (pre.dataset.sourceLine) {
+                container.dataset.sourceLine = pre.dataset.sourceLine;
+            }
+            pre.parentNode.replaceChild(container, pre);
+
+            renderDiagram(container, codeBlocks[i].textContent);
+        }
+    }
+
+    function renderDiagram(container, source) {
+        var id = 'mermaid-diagram-' + diagramCount++;
+
+        // Since mermaid 10, `render` returns a promise instead of calling a callback.
+        mermaid.render(id, source).then(function(result) {
+            container.innerHTML = result.svg;
+        }).catch(function(error) {
+            // Mermaid leaves an error diagram at the end of the body when rendering fails.
+            var errorDiagram = document.getElementById('d' + id);
+            if (errorDiagram) {
+                errorDiagram.remove();
+            }
+
+            // Show the error in place of the diagram, followed by its source.
+            var message = document.createElement('div');
+            message.className = 'mermaid-error';
+            message.textContent = error && error.message ? error.message : String(error);
+
+            var code = document.createElement('pre');
+            code.textContent = source;
+
+            container.replaceChildren(message, code);
+        });
+    }
 
-    syntaxHighlight();
-    renderMath();
     var previewWindow = document.getElementById('markdown-preview');
-    var webSocketUrl = 'ws://' + window.location.host;
 
-    var socket = new ReconnectingWebSocket(webSocketUrl);
+    // The table of contents is present if it is enabled by the server.
+    var toc = document.getElementById('toc');
+    var tocDetails = toc && toc.querySelector('details');
+
+    // The table of contents is a sidebar on wide screens, which must stay open, and collapsed
+    // above the article otherwise. The width matches the media query in styles.css.
+    var tocSidebar = window.matchMedia('(min-width: 1100px)');
+
+    function updateTocLayout() {
+        tocDetails.open = tocSidebar.matches;
+    }
+
+    if (toc) {
+        updateTocLayout();
+        tocSidebar.addEventListener('change', updateTocLayout);
+    }
+
+    // Rebuild the table of contents as nested lists of links to the headings that have ids. A
+    // heading is nested under the closest preceding heading of a higher level.
+    function buildToc() {
+        if (!toc) {
+            return;
+        }
+
+        var root = document.createElement('ul');
+        var lists = [{ level: 0, list: root }];
+
+        var headings = previewWindow.querySelectorAll(
+            'h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]');
+        for (var i = 0; i < headings.length; i++) {
+            var level = parseInt(headings[i].tagName.substring(1), 10);
+
+            while (lists[lists.length - 1].level >= level) {
+                lists.pop();
+            }
+
+            var link = document.createElement('a');
+            link.href = '#' + encodeURIComponent(headings[i].id);
+            link.textContent = headings[i].textContent;
+
+            var item = document.createElement('li');
+            item.appendChild(link);
+            lists[lists.length - 1].list.appendChild(item);
+
+            var children = document.createElement('ul');
+            item.appendChild(children);
+            lists.push({ level: level, list: children });
+        }
+
+        // Remove the lists of headings without subheadings.
+        var empty = root.querySelectorAll('ul:empty');
+        for (var i = 0; i < empty.length; i++) {
+            empty[i].remove();
+        }
+
+        tocDetails.replaceChildren(tocDetails.querySelector('summary'), root);
+        toc.hidden = headings.length === 0;
+    }
+
+    renderDiagrams(previewWindow);
+    syntaxHighlight(previewWindow);
+    renderMath(previewWindow);
+    buildToc();
+
+    // Ask the server for the full HTML, since the preview no longer matches the blocks that the
+    // server diffs against.
+    function requestResync() {
+        socket.send(JSON.stringify({ type: 'resync' }));
+    }
+
+    // Replace only the top-level elements that changed, so that the rest of the document keeps
+    // its scroll position and is not highlighted again.
+    function applyPatch(patch) {
+        var blocks = previewWindow.children;
+
+        // The browser may parse raw HTML into different top-level elements than the server, such
+        // as a `div` inside a `p`, in which case the indices of the patch do not apply.
+        if (blocks.length !== patch.blocks - patch.insert.length + patch.delete) {
+            requestResync();
+            return;
+        }
+
+        for (var i = 0; i < patch.delete; i++) {
+            previewWindow.removeChild(blocks[patch.start]);
+        }
+
+        var template = document.createElement('template');
+        template.innerHTML = patch.insert.join('\n');
+        renderDiagrams(template.content);
+        var inserted = Array.prototype.slice.call(template.content.children);
+
+        previewWindow.insertBefore(template.content, blocks[patch.start] || null);
+
+        for (var i = 0; i < inserted.length; i++) {
+            syntaxHighlight(inserted[i]);
+            renderMath(inserted[i]);
+        }
+
+        if (blocks.length !== patch.blocks) {
+            requestResync();
+        }
+    }
+    var webSocketUrl = 'ws://' + window.location.host + window.location.pathname;
+
+    var socket = new ReconnectingWebSocket(webSocketUrl, ['aurelius.v1']);
     socket.maxReconnectInterval = 5000;
 
+    var errorBanner = document.getElementById('render-error');
+    var errorMessage = document.getElementById('render-error-message');
+
+    function showError(message) {
+        errorMessage.textContent = message;
+        errorBanner.hidden = false;
+    }
+
+    function hideError() {
+        errorBanner.hidden = true;
+    }
+
+    document.getElementById('render-error-dismiss').onclick = hideError;
+
+    // Scroll to the last block that begins on or before the given line of the markdown.
+    function scrollToLine(line) {
+        var target = null;
+        var blocks = previewWindow.querySelectorAll('[data-source-line]');
+
+        for (var i = 0; i < blocks.length; i++) {
+            if (parseInt(blocks[i].dataset.sourceLine, 10) > line) {
+                break;
+            }
+            target = blocks[i];
+        }
+
+        if (target) {
+            target.scrollIntoView({ block: 'center' });
+        }
+    }
+
+    // Report clicked task list checkboxes, and the source line of other clicked blocks so that
+    // editors can jump to them.
+    previewWindow.addEventListener('click', function(event) {
+        if (event.target.matches('input[type="checkbox"][data-source-offset]')) {
+            // The checkbox keeps its state until the host resends the edited markdown. `checked`
+            // is the requested state until the default action is undone after this handler.
+            event.preventDefault();
+            socket.send(JSON.stringify({
+                type: 'task_toggle',
+                offset: parseInt(event.target.dataset.sourceOffset, 10),
+                checked: event.target.checked,
+            }));
+            return;
+        }
+
+        var block = event.target.closest('[data-source-line]');
+
+        if (block) {
+            socket.send(JSON.stringify({
+                type: 'click',
+                line: parseInt(block.dataset.sourceLine, 10),
+            }));
+        }
+    });
+
     socket.onmessage = function(event) {
-        document.getElementById('markdown-preview').innerHTML = event.data;
-        syntaxHighlight();
-        renderMath();
+        var message = JSON.parse(event.data);
+
+        switch (message.type) {
+            case 'html':
+                previewWindow.innerHTML = message.html;
+                hideError();
+                renderDiagrams(previewWindow);
+                syntaxHighlight(previewWindow);
+                renderMath(previewWindow);
+                buildToc();
+                break;
+            case 'patch':
+                applyPatch(message);
+                hideError();
+                buildToc();
+                break;
+            case 'metadata':
+                var title = message.metadata && message.metadata.title;
+                document.title = typeof title === 'string' ? title : 'Markdown Composer';
+                break;
+            case 'error':
+                // Keep the last good render visible underneath the error.
+                showError(message.message);
+                break;
+            case 'error_cleared':
+                hideError();
+                break;
+            case 'scroll':
+                scrollToLine(message.line);
+                break;
+        }
     }
 
     socket.onclose = function(event) {
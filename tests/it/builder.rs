@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fs;
+
+use aurelius::Server;
+use reqwest::StatusCode;
+use tempfile::NamedTempFile;
+use tokio::net::lookup_host;
+
+async fn localhost() -> anyhow::Result<std::net::SocketAddr> {
+    Ok(lookup_host("localhost:0").await?.next().unwrap())
+}
+
+#[tokio::test]
+async fn configure_before_bind() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = tempfile::tempdir()?;
+    fs::write(tmp_dir.path().join("file.txt"), "Lorem ipsum")?;
+
+    let css_file = NamedTempFile::new()?;
+    fs::write(&css_file, "a { color: #FF0000; }")?;
+
+    let server = Server::builder()
+        .static_root(tmp_dir.path())
+        .custom_css(vec![css_file.path().display().to_string()])
+        .bind(&localhost().await?)
+        .await?;
+
+    let res = reqwest::get(&format!("http://{}/file.txt", server.addr())).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await?, "Lorem ipsum");
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(text.contains("<style>a { color: #FF0000; }</style>"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_highlight_theme() -> Result<(), Box<dyn Error>> {
+    let err = Server::builder()
+        .highlight_theme("not-a-theme")
+        .bind(&localhost().await?)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, aurelius::Error::Theme(theme) if theme == "not-a-theme"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_css_file() -> Result<(), Box<dyn Error>> {
+    let err = Server::builder()
+        .custom_css(vec![String::from("/non-existent/styles.css")])
+        .bind(&localhost().await?)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, aurelius::Error::Css { .. }));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_external_renderer() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    let err = Server::builder()
+        .external_renderer(Command::new("aurelius-nonexistent-renderer"))
+        .bind(&localhost().await?)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, aurelius::Error::RendererSpawn(_)));
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn external_renderer_on_path() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    Server::builder()
+        .external_renderer(Command::new("cat"))
+        .bind(&localhost().await?)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn external_renderer_not_executable() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    let file = NamedTempFile::new()?;
+
+    let err = Server::builder()
+        .external_renderer(Command::new(file.path()))
+        .bind(&localhost().await?)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, aurelius::Error::RendererSpawn(_)));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn external_renderer_in_current_dir() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::process::Command;
+
+    let dir = tempfile::tempdir()?;
+    let script = dir.path().join("render.sh");
+    fs::write(&script, "#!/bin/sh\ncat\n")?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+
+    let mut command = Command::new("./render.sh");
+    command.current_dir(dir.path());
+
+    Server::builder()
+        .external_renderer(command)
+        .bind(&localhost().await?)
+        .await?;
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::net::SocketAddr;
 
 use reqwest::StatusCode;
 use tokio::fs;
@@ -100,6 +101,75 @@ async fn static_files() -> Result<(), Box<dyn Error>> {
     assert_eq!(res.headers()["Content-Type"], "application/javascript");
     res.text().await?;
 
+    // Prebuilt releases vendored by scripts/vendor.sh.
+    for path in [
+        "vendor/katex/dist/katex.min.js",
+        "vendor/katex/dist/katex.min.css",
+        "vendor/katex/dist/contrib/auto-render.min.js",
+        "vendor/mermaid/dist/mermaid.min.js",
+    ] {
+        let res = reqwest::get(&format!("http://{}/__/{}", addr, path)).await?;
+        assert!(res.status().is_success(), "{} not found", path);
+    }
+
+    Ok(())
+}
+
+/// Returns the URL of the asset on `page` whose URL ends with `file`.
+fn asset_url<'a>(page: &'a str, file: &str) -> &'a str {
+    let end = page.find(&format!("{}\"", file)).unwrap() + file.len();
+    let start = page[..end].rfind('"').unwrap() + 1;
+    &page[start..end]
+}
+
+/// Checks that a vendored asset is served, or loaded from jsDelivr if it was not vendored.
+async fn assert_vendored(addr: SocketAddr, url: &str, cdn_url: &str) -> Result<(), Box<dyn Error>> {
+    match url.strip_prefix("/__/") {
+        Some(_) => {
+            let res = reqwest::get(&format!("http://{}{}", addr, url)).await?;
+            assert!(res.status().is_success(), "{} is not served", url);
+        }
+        None => assert_eq!(url, cdn_url),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn katex_assets() -> Result<(), Box<dyn Error>> {
+    let server = new_server().await?;
+    let addr = server.addr();
+
+    let page = reqwest::get(&format!("http://{}", addr))
+        .await?
+        .text()
+        .await?;
+
+    for file in [
+        "katex.min.js",
+        "katex.min.css",
+        "contrib/auto-render.min.js",
+    ] {
+        let cdn_url = format!("https://cdn.jsdelivr.net/npm/katex@0.16.9/dist/{}", file);
+        assert_vendored(addr, asset_url(&page, file), &cdn_url).await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mermaid_assets() -> Result<(), Box<dyn Error>> {
+    let server = new_server().await?;
+    let addr = server.addr();
+
+    let page = reqwest::get(&format!("http://{}", addr))
+        .await?
+        .text()
+        .await?;
+
+    let cdn_url = "https://cdn.jsdelivr.net/npm/mermaid@10.9.1/dist/mermaid.min.js";
+    assert_vendored(addr, asset_url(&page, "mermaid.min.js"), cdn_url).await?;
+
     Ok(())
 }
 
@@ -117,7 +187,7 @@ async fn partial_read() -> Result<(), Box<dyn Error>> {
     let mut conn = socket.connect(server.addr()).await?;
 
     let partial_req = "GET /__/css/styles.css HTTP/1.1\r\n\r\n";
-    conn.write(partial_req.as_bytes()).await?;
+    conn.write_all(partial_req.as_bytes()).await?;
     conn.flush().await?;
 
     let _ = conn.read(&mut []).await?;
@@ -1,11 +1,21 @@
+use async_tungstenite::tungstenite::Message;
 use tokio::net::lookup_host;
 
 use aurelius::Server;
 
+mod builder;
 mod files;
 mod options;
+mod render;
 
 async fn new_server() -> anyhow::Result<Server> {
     let addr = lookup_host("localhost:0").await?.next().unwrap();
     Ok(Server::bind(&addr).await?)
 }
+
+/// Extracts the HTML from an HTML message.
+fn html(message: &Message) -> String {
+    let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+    assert_eq!(message["type"], "html");
+    message["html"].as_str().unwrap().to_owned()
+}
@@ -4,7 +4,7 @@ use std::fs;
 use futures_util::TryStreamExt;
 use tempfile::NamedTempFile;
 
-use crate::new_server;
+use crate::{html, new_server};
 
 #[tokio::test]
 async fn custom_css_url() -> Result<(), Box<dyn Error>> {
@@ -19,7 +19,7 @@ async fn custom_css_url() -> Result<(), Box<dyn Error>> {
         .await?
         .text()
         .await?;
-    assert!(text.contains(&CSS_URL));
+    assert!(text.contains(CSS_URL));
     assert!(!text.contains("github-markdown.css"));
 
     Ok(())
@@ -63,6 +63,18 @@ async fn custom_css_file_uri() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[tokio::test]
+async fn custom_css_missing_file() -> Result<(), Box<dyn Error>> {
+    let mut server = new_server().await?;
+
+    let err = server
+        .set_custom_css(vec![String::from("/non-existent/styles.css")])
+        .unwrap_err();
+    assert!(matches!(err, aurelius::Error::Css { .. }));
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn custom_css_default() -> Result<(), Box<dyn Error>> {
     let server = new_server().await?;
@@ -105,7 +117,196 @@ async fn external_renderer() -> Result<(), Box<dyn Error>> {
     server.send("Hello, world!").await?;
 
     let message = websocket.try_next().await?.unwrap();
-    assert_eq!(message.to_text()?.trim(), "Hello, world!");
+    assert_eq!(html(&message).trim(), "Hello, world!");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn custom_renderer() -> Result<(), Box<dyn Error>> {
+    use async_trait::async_trait;
+    use aurelius::Renderer;
+
+    #[derive(Debug)]
+    struct Uppercase;
+
+    #[async_trait]
+    impl Renderer for Uppercase {
+        async fn render(&self, markdown: &str, html: &mut String) -> aurelius::Result<()> {
+            html.push_str(&markdown.to_uppercase());
+            Ok(())
+        }
+    }
+
+    let mut server = new_server().await?;
+
+    server.set_renderer(Uppercase);
+
+    let (mut websocket, _) =
+        async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+    server.send("Hello, world!").await?;
+
+    let message = websocket.try_next().await?.unwrap();
+    assert_eq!(html(&message), "HELLO, WORLD!");
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn render_timeout() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+
+    use tokio::process::Command;
+
+    let mut server = new_server().await?;
+
+    let mut sleep = Command::new("sleep");
+    sleep.arg("10");
+
+    server.set_external_renderer(sleep);
+    server.set_render_timeout(Some(Duration::from_millis(100)));
+
+    let start = Instant::now();
+    let err = server.send("Hello, world!").await.unwrap_err();
+    assert!(matches!(err, aurelius::Error::RendererTimeout));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn raw_html_fallback() -> Result<(), Box<dyn Error>> {
+    use async_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let mut server = new_server().await?;
+    server.set_raw_html_fallback(true);
+
+    let (mut raw, _) =
+        async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+    let mut request = format!("ws://{}", server.addr()).into_client_request()?;
+    request
+        .headers_mut()
+        .insert("Sec-WebSocket-Protocol", "aurelius.v1".parse()?);
+    let (mut json, _) = async_tungstenite::tokio::connect_async(request).await?;
+
+    server.send("<p>Hello, world!</p>").await?;
+
+    let message = raw.try_next().await?.unwrap();
+    assert_eq!(message.to_text()?, "<p>Hello, world!</p>");
+
+    let message = json.try_next().await?.unwrap();
+    assert_eq!(html(&message), "<p>Hello, world!</p>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn debounce() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let mut server = new_server().await?;
+    server.set_debounce(Some(Duration::from_millis(100)));
+
+    let (mut websocket, _) =
+        async_tungstenite::tokio::connect_async(format!("ws://{}", server.addr())).await?;
+
+    let (first, second, third) = tokio::join!(
+        server.send("<p>First</p>"),
+        server.send("<p>Second</p>"),
+        server.send("<p>Third</p>"),
+    );
+    first?;
+    second?;
+    third?;
+
+    let message = websocket.try_next().await?.unwrap();
+    assert_eq!(html(&message), "<p>Third</p>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn math() -> Result<(), Box<dyn Error>> {
+    use aurelius::MathDelimiter;
+
+    let mut server = new_server().await?;
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    // Where KaTeX is loaded from is tested by `files::katex_assets`.
+    assert!(text.contains("/katex.min.js\""));
+    assert!(!text.contains("cdnjs"));
+    assert!(text.contains(r#"{&quot;left&quot;:&quot;$$&quot;,&quot;right&quot;:&quot;$$&quot;,&quot;display&quot;:true}"#));
+
+    server.set_math_delimiters(vec![MathDelimiter::inline("$`", "`$")]);
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(text.contains(
+        r#"data-math-delimiters="[{&quot;left&quot;:&quot;$&#x60;&quot;,&quot;right&quot;:&quot;&#x60;$&quot;,&quot;display&quot;:false}]""#
+    ));
+
+    server.set_math(false);
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(!text.contains("katex"));
+    assert!(!text.contains("data-math-delimiters"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mermaid() -> Result<(), Box<dyn Error>> {
+    let mut server = new_server().await?;
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    // Where mermaid is loaded from is tested by `files::mermaid_assets`.
+    assert!(text.contains("/mermaid.min.js\""));
+    assert!(text.contains("data-mermaid"));
+
+    server.set_mermaid(false);
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(!text.contains("mermaid"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn toc() -> Result<(), Box<dyn Error>> {
+    let mut server = new_server().await?;
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(!text.contains(r#"id="toc""#));
+    assert!(!text.contains("has-toc"));
+
+    server.set_toc(true);
+
+    let text = reqwest::get(&format!("http://{}", server.addr()))
+        .await?
+        .text()
+        .await?;
+    assert!(text.contains(r#"<body class="has-toc">"#));
+    assert!(text.contains(r#"<nav class="toc" id="toc""#));
 
     Ok(())
 }
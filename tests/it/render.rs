@@ -0,0 +1,987 @@
+use std::error::Error;
+
+use aurelius::{
+    BlockProcessor, CommandProcessor, ExternalRenderer, Framing, MarkdownOptions, MarkdownRenderer,
+    PersistentRenderer, Renderer,
+};
+
+async fn render(renderer: &impl Renderer, markdown: &str) -> Result<String, Box<dyn Error>> {
+    let mut html = String::new();
+    renderer.render(markdown, &mut html).await?;
+    Ok(html)
+}
+
+#[tokio::test]
+async fn default_extensions() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new();
+
+    let html = render(&renderer, "~~struck~~").await?;
+    assert_eq!(
+        html.trim(),
+        r#"<p data-source-line="1"><del>struck</del></p>"#
+    );
+
+    let html = render(&renderer, "- [x] done").await?;
+    assert!(html.contains(r#"<input disabled="" type="checkbox" checked=""/>"#));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn smart_punctuation() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().options(MarkdownOptions::ENABLE_SMART_PUNCTUATION);
+
+    let html = render(&renderer, r#""Hello"..."#).await?;
+    assert_eq!(html.trim(), r#"<p data-source-line="1">“Hello”…</p>"#);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn heading_attributes() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().options(MarkdownOptions::ENABLE_HEADING_ATTRIBUTES);
+
+    let html = render(&renderer, "# Heading {#id .class}").await?;
+    assert_eq!(
+        html.trim(),
+        r##"<h1 id="id" class="class" data-source-line="1"><a class="anchor" href="#id" aria-hidden="true"></a>Heading</h1>"##
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn disable_extensions() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().options(MarkdownOptions::empty());
+
+    let html = render(&renderer, "~~struck~~").await?;
+    assert_eq!(html.trim(), r#"<p data-source-line="1">~~struck~~</p>"#);
+
+    let html = render(&renderer, "| a |\n| - |\n| b |").await?;
+    assert!(!html.contains("<table>"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn source_lines() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new();
+
+    let html = render(
+        &renderer,
+        "# Heading\n\n- one\n- two\n\n```rust\nfn main() {}\n```\n\n---\n\n> quote",
+    )
+    .await?;
+    assert!(html.contains(r#"<h1 id="heading" data-source-line="1">"#));
+    assert!(html.contains(r#"<ul data-source-line="3">"#));
+    assert!(html.contains(r#"<li data-source-line="4">two</li>"#));
+    assert!(html.contains(r#"<pre data-source-line="6"><code class="language-rust">"#));
+    assert!(html.contains(r#"<hr data-source-line="10" />"#));
+    assert!(html.contains(r#"<blockquote data-source-line="12">"#));
+    assert!(html.contains(r#"<p data-source-line="12">quote</p>"#));
+
+    let renderer = MarkdownRenderer::new().source_lines(false);
+
+    let html = render(&renderer, "# Heading").await?;
+    assert_eq!(
+        html.trim(),
+        r##"<h1 id="heading"><a class="anchor" href="#heading" aria-hidden="true"></a>Heading</h1>"##
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn heading_ids() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .options(MarkdownOptions::ENABLE_HEADING_ATTRIBUTES);
+
+    let html = render(
+        &renderer,
+        "# Usage {#getting-started}\n# Getting Started!\n## `render()` & *friends*\n## Getting started\n# Über\n# ???",
+    )
+    .await?;
+    assert_eq!(
+        html,
+        concat!(
+            r##"<h1 id="getting-started"><a class="anchor" href="#getting-started" aria-hidden="true"></a>Usage</h1>"##,
+            "\n",
+            r##"<h1 id="getting-started-1"><a class="anchor" href="#getting-started-1" aria-hidden="true"></a>Getting Started!</h1>"##,
+            "\n",
+            r##"<h2 id="render--friends"><a class="anchor" href="#render--friends" aria-hidden="true"></a><code>render()</code> &amp; <em>friends</em></h2>"##,
+            "\n",
+            r##"<h2 id="getting-started-2"><a class="anchor" href="#getting-started-2" aria-hidden="true"></a>Getting started</h2>"##,
+            "\n",
+            r##"<h1 id="über"><a class="anchor" href="#%C3%BCber" aria-hidden="true"></a>Über</h1>"##,
+            "\n",
+            "<h1>???</h1>\n",
+        )
+    );
+
+    let renderer = MarkdownRenderer::new().heading_ids(false);
+
+    let html = render(&renderer, "# Heading").await?;
+    assert_eq!(html.trim(), r#"<h1 data-source-line="1">Heading</h1>"#);
+
+    let renderer = renderer.source_lines(false);
+
+    let html = render(&renderer, "# Heading").await?;
+    assert_eq!(html.trim(), "<h1>Heading</h1>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn interactive_tasks() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().interactive_tasks(true);
+
+    let html = render(&renderer, "- [ ] todo\n- [x] done").await?;
+    assert!(html.contains(r#"<input type="checkbox" data-source-offset="2"/>"#));
+    assert!(html.contains(r#"<input type="checkbox" data-source-offset="13" checked=""/>"#));
+    assert!(!html.contains("disabled"));
+
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .interactive_tasks(true);
+
+    let html = render(&renderer, "- [ ] todo").await?;
+    assert_eq!(
+        html,
+        "<ul>\n<li><input type=\"checkbox\" data-source-offset=\"2\"/>\ntodo</li>\n</ul>\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mathml() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().source_lines(false).mathml(true);
+
+    let html = render(&renderer, "Euler: $e^{i\\pi} = -1$").await?;
+    assert_eq!(
+        html,
+        "<p>Euler: <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow>\
+         <msup><mi>e</mi><mrow><mi>i</mi><mi>\u{3c0}</mi></mrow></msup>\
+         <mo>=</mo><mo>\u{2212}</mo><mn>1</mn></mrow></math></p>\n"
+    );
+
+    let html = render(&renderer, "$$\n\\frac{a_1 * b_2}{\\sqrt{x}}\n$$").await?;
+    assert_eq!(
+        html,
+        "<p><math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><mfrac>\
+         <mrow><msub><mi>a</mi><mn>1</mn></msub><mo>*</mo><msub><mi>b</mi><mn>2</mn></msub></mrow>\
+         <msqrt><mi>x</mi></msqrt></mfrac></math></p>\n"
+    );
+
+    let html = render(&renderer, "`$x$` \\$5 and $10, $ x $").await?;
+    assert_eq!(html, "<p><code>$x$</code> $5 and $10, $ x $</p>\n");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mathml_error() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().source_lines(false).mathml(true);
+
+    let html = render(&renderer, "Before $\\frac{1}{$ after *emphasis*").await?;
+    assert_eq!(
+        html,
+        "<p>Before <code class=\"math-error\" title=\"missing }\">$\\frac{1}{$</code> after \
+         <em>emphasis</em></p>\n"
+    );
+
+    Ok(())
+}
+
+/// Renders `tex` as inline or display math, returning the contents of the `math` element.
+async fn render_math(tex: &str, display: bool) -> Result<String, Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().source_lines(false).mathml(true);
+
+    let (markdown, prefix) = if display {
+        (
+            format!("$${}$$", tex),
+            r#"<p><math xmlns="http://www.w3.org/1998/Math/MathML" display="block">"#,
+        )
+    } else {
+        (
+            format!("${}$", tex),
+            r#"<p><math xmlns="http://www.w3.org/1998/Math/MathML">"#,
+        )
+    };
+
+    let html = render(&renderer, &markdown).await?;
+    match html
+        .strip_prefix(prefix)
+        .and_then(|html| html.strip_suffix("</math></p>\n"))
+    {
+        Some(mathml) => Ok(mathml.to_owned()),
+        None => Err(format!("{:?} was not rendered as math: {}", tex, html).into()),
+    }
+}
+
+#[tokio::test]
+async fn mathml_constructs() -> Result<(), Box<dyn Error>> {
+    let cases = [
+        // Identifiers, numbers and operators.
+        ("x", "<mi>x</mi>"),
+        ("3.14", "<mn>3.14</mn>"),
+        (
+            "x+y=z",
+            "<mrow><mi>x</mi><mo>+</mo><mi>y</mi><mo>=</mo><mi>z</mi></mrow>",
+        ),
+        ("a-b", "<mrow><mi>a</mi><mo>\u{2212}</mo><mi>b</mi></mrow>"),
+        ("a < b", "<mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>"),
+        ("f'", "<mrow><mi>f</mi><mo>\u{2032}</mo></mrow>"),
+        // Scripts.
+        ("x^2", "<msup><mi>x</mi><mn>2</mn></msup>"),
+        ("x_i", "<msub><mi>x</mi><mi>i</mi></msub>"),
+        ("x_i^2", "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"),
+        (
+            "x^{n+1}",
+            "<msup><mi>x</mi><mrow><mi>n</mi><mo>+</mo><mn>1</mn></mrow></msup>",
+        ),
+        ("^2", "<msup><mrow></mrow><mn>2</mn></msup>"),
+        (
+            "x_10",
+            "<mrow><msub><mi>x</mi><mn>1</mn></msub><mn>0</mn></mrow>",
+        ),
+        // Fractions and roots.
+        ("\\frac12", "<mfrac><mn>1</mn><mn>2</mn></mfrac>"),
+        ("\\frac{a}{b}", "<mfrac><mi>a</mi><mi>b</mi></mfrac>"),
+        (
+            "\\binom{n}{k}",
+            "<mrow><mo>(</mo><mfrac linethickness=\"0\"><mi>n</mi><mi>k</mi></mfrac><mo>)</mo></mrow>",
+        ),
+        ("\\sqrt{x}", "<msqrt><mi>x</mi></msqrt>"),
+        ("\\sqrt[3]{x}", "<mroot><mi>x</mi><mn>3</mn></mroot>"),
+        // Text and fonts.
+        (
+            "\\text{if } x",
+            "<mrow><mtext>if </mtext><mi>x</mi></mrow>",
+        ),
+        (
+            "\\operatorname{rank} A",
+            "<mrow><mi>rank</mi><mi>A</mi></mrow>",
+        ),
+        (
+            "\\mathrm{d}x",
+            "<mrow><mi mathvariant=\"normal\">d</mi><mi>x</mi></mrow>",
+        ),
+        ("\\mathbf{v}", "<mi>\u{1d42f}</mi>"),
+        (
+            "\\mathbf{x2}",
+            "<mrow><mi>\u{1d431}</mi><mn>\u{1d7d0}</mn></mrow>",
+        ),
+        ("\\mathbb{R}", "<mi>\u{211d}</mi>"),
+        ("\\mathcal{L}", "<mi>\u{2112}</mi>"),
+        ("\\mathfrak{g}", "<mi>\u{1d524}</mi>"),
+        ("\\mathsf{A}", "<mi>\u{1d5a0}</mi>"),
+        ("\\mathtt{a}", "<mi>\u{1d68a}</mi>"),
+        // Delimiters.
+        (
+            "\\left( x \\right)",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>x</mi>\
+             <mo fence=\"true\" stretchy=\"true\">)</mo></mrow>",
+        ),
+        (
+            "\\left. x \\right|",
+            "<mrow><mi>x</mi><mo fence=\"true\" stretchy=\"true\">|</mo></mrow>",
+        ),
+        (
+            "\\left\\langle x \\right\\rangle",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">\u{27e8}</mo><mi>x</mi>\
+             <mo fence=\"true\" stretchy=\"true\">\u{27e9}</mo></mrow>",
+        ),
+        (
+            "\\|x\\|",
+            "<mrow><mo>\u{2016}</mo><mi>x</mi><mo>\u{2016}</mo></mrow>",
+        ),
+        // Environments.
+        (
+            "\\begin{matrix} a & b \\\\ c & d \\end{matrix}",
+            "<mrow><mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+             <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable></mrow>",
+        ),
+        (
+            "\\begin{pmatrix} a \\end{pmatrix}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable><mtr><mtd><mi>a</mi></mtd>\
+             </mtr></mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>",
+        ),
+        (
+            "\\begin{bmatrix} a \\end{bmatrix}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">[</mo><mtable><mtr><mtd><mi>a</mi></mtd>\
+             </mtr></mtable><mo fence=\"true\" stretchy=\"true\">]</mo></mrow>",
+        ),
+        (
+            "\\begin{Bmatrix} a \\end{Bmatrix}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">{</mo><mtable><mtr><mtd><mi>a</mi></mtd>\
+             </mtr></mtable><mo fence=\"true\" stretchy=\"true\">}</mo></mrow>",
+        ),
+        (
+            "\\begin{vmatrix} a \\end{vmatrix}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">|</mo><mtable><mtr><mtd><mi>a</mi></mtd>\
+             </mtr></mtable><mo fence=\"true\" stretchy=\"true\">|</mo></mrow>",
+        ),
+        (
+            "\\begin{Vmatrix} a \\end{Vmatrix}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">\u{2016}</mo><mtable><mtr><mtd><mi>a</mi>\
+             </mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">\u{2016}</mo></mrow>",
+        ),
+        (
+            "\\begin{cases} 1 & x > 0 \\\\ 0 & \\text{otherwise} \\end{cases}",
+            "<mrow><mo fence=\"true\" stretchy=\"true\">{</mo><mtable columnalign=\"left\">\
+             <mtr><mtd><mn>1</mn></mtd><mtd><mrow><mi>x</mi><mo>&gt;</mo><mn>0</mn></mrow></mtd>\
+             </mtr><mtr><mtd><mn>0</mn></mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable>\
+             </mrow>",
+        ),
+        (
+            "\\begin{aligned} a &= b \\\\ &= c \\end{aligned}",
+            "<mrow><mtable columnalign=\"right left\"><mtr><mtd><mi>a</mi></mtd>\
+             <mtd><mrow><mo>=</mo><mi>b</mi></mrow></mtd></mtr><mtr><mtd><mrow></mrow></mtd>\
+             <mtd><mrow><mo>=</mo><mi>c</mi></mrow></mtd></mtr></mtable></mrow>",
+        ),
+        // Spacing.
+        (
+            "a\\,b",
+            "<mrow><mi>a</mi><mspace width=\"0.1667em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\:b",
+            "<mrow><mi>a</mi><mspace width=\"0.2222em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\;b",
+            "<mrow><mi>a</mi><mspace width=\"0.2778em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\!b",
+            "<mrow><mi>a</mi><mspace width=\"-0.1667em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\ b",
+            "<mrow><mi>a</mi><mspace width=\"0.3333em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a~b",
+            "<mrow><mi>a</mi><mspace width=\"0.3333em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\quad b",
+            "<mrow><mi>a</mi><mspace width=\"1em\"/><mi>b</mi></mrow>",
+        ),
+        (
+            "a\\qquad b",
+            "<mrow><mi>a</mi><mspace width=\"2em\"/><mi>b</mi></mrow>",
+        ),
+        // Accents.
+        (
+            "\\hat{x}",
+            "<mover accent=\"true\"><mi>x</mi><mo>^</mo></mover>",
+        ),
+        (
+            "\\bar{x}",
+            "<mover accent=\"true\"><mi>x</mi><mo>\u{203e}</mo></mover>",
+        ),
+        (
+            "\\vec{v}",
+            "<mover accent=\"true\"><mi>v</mi><mo>\u{2192}</mo></mover>",
+        ),
+        (
+            "\\dot{x}",
+            "<mover accent=\"true\"><mi>x</mi><mo>\u{2d9}</mo></mover>",
+        ),
+        (
+            "\\ddot{x}",
+            "<mover accent=\"true\"><mi>x</mi><mo>\u{a8}</mo></mover>",
+        ),
+        (
+            "\\tilde{x}",
+            "<mover accent=\"true\"><mi>x</mi><mo>~</mo></mover>",
+        ),
+        (
+            "\\overline{AB}",
+            "<mover accent=\"true\"><mrow><mi>A</mi><mi>B</mi></mrow><mo>\u{203e}</mo></mover>",
+        ),
+        // Symbols.
+        (
+            "\\alpha\\beta\\Gamma",
+            "<mrow><mi>\u{3b1}</mi><mi>\u{3b2}</mi><mi>\u{393}</mi></mrow>",
+        ),
+        ("\\infty", "<mi>\u{221e}</mi>"),
+        (
+            "\\le\\ge\\ne",
+            "<mrow><mo>\u{2264}</mo><mo>\u{2265}</mo><mo>\u{2260}</mo></mrow>",
+        ),
+        ("\\to", "<mo>\u{2192}</mo>"),
+        (
+            "\\cdot\\times",
+            "<mrow><mo>\u{22c5}</mo><mo>\u{d7}</mo></mrow>",
+        ),
+        ("\\ldots", "<mo>\u{2026}</mo>"),
+        (
+            "\\{ \\} \\$ \\% \\& \\# \\_",
+            "<mrow><mo>{</mo><mo>}</mo><mo>$</mo><mo>%</mo><mo>&amp;</mo><mo>#</mo><mo>_</mo></mrow>",
+        ),
+        // Functions and large operators, whose scripts are beside them in inline math.
+        ("\\sin x", "<mrow><mi>sin</mi><mi>x</mi></mrow>"),
+        (
+            "\\log_2 n",
+            "<mrow><msub><mi>log</mi><mn>2</mn></msub><mi>n</mi></mrow>",
+        ),
+        (
+            "\\lim_{n \\to \\infty} a_n",
+            "<mrow><msub><mi>lim</mi><mrow><mi>n</mi><mo>\u{2192}</mo><mi>\u{221e}</mi></mrow>\
+             </msub><msub><mi>a</mi><mi>n</mi></msub></mrow>",
+        ),
+        (
+            "\\sum_{i=1}^n i",
+            "<mrow><msubsup><mo>\u{2211}</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow>\
+             <mi>n</mi></msubsup><mi>i</mi></mrow>",
+        ),
+        (
+            "\\operatorname*{argmax}_x f",
+            "<mrow><msub><mi>argmax</mi><mi>x</mi></msub><mi>f</mi></mrow>",
+        ),
+    ];
+
+    for (tex, mathml) in cases {
+        assert_eq!(render_math(tex, false).await?, mathml, "{:?}", tex);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mathml_display_limits() -> Result<(), Box<dyn Error>> {
+    let cases = [
+        (
+            "\\lim_{n \\to \\infty} a_n",
+            "<mrow><munder><mi>lim</mi><mrow><mi>n</mi><mo>\u{2192}</mo><mi>\u{221e}</mi></mrow>\
+             </munder><msub><mi>a</mi><mi>n</mi></msub></mrow>",
+        ),
+        (
+            "\\sum_{i=1}^n i",
+            "<mrow><munderover><mo>\u{2211}</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow>\
+             <mi>n</mi></munderover><mi>i</mi></mrow>",
+        ),
+        (
+            "\\operatorname*{argmax}_x f",
+            "<mrow><munder><mi>argmax</mi><mi>x</mi></munder><mi>f</mi></mrow>",
+        ),
+        // Integrals keep their limits beside them.
+        (
+            "\\int_0^1 f",
+            "<mrow><msubsup><mo>\u{222b}</mo><mn>0</mn><mn>1</mn></msubsup><mi>f</mi></mrow>",
+        ),
+    ];
+
+    for (tex, mathml) in cases {
+        assert_eq!(render_math(tex, true).await?, mathml, "{:?}", tex);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mathml_errors() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().source_lines(false).mathml(true);
+
+    let cases = [
+        ("x^2^3", "double superscript"),
+        ("x_1_2", "double subscript"),
+        ("\\unknown", "unknown command \\unknown"),
+        ("\\right)", "\\right without \\left"),
+        ("\\left( x", "missing \\right"),
+        ("\\left\\foo x \\right)", "invalid delimiter \\foo"),
+        ("\\frac{1}{", "missing }"),
+        ("\\frac{1}", "missing argument"),
+        ("\\sqrt[3", "missing ]"),
+        ("\\text x", "expected {"),
+        ("}", "unexpected }"),
+        ("a & b", "unexpected &amp;"),
+        ("a \\\\ b", "line breaks are only allowed in environments"),
+        ("\\end{matrix}", "\\end without \\begin"),
+        ("\\begin{foo} a \\end{foo}", "unknown environment foo"),
+        (
+            "\\begin{matrix} a \\end{pmatrix}",
+            "expected \\end{matrix}, found \\end{pmatrix}",
+        ),
+    ];
+
+    for (tex, error) in cases {
+        let html = render(&renderer, &format!("${}$", tex)).await?;
+        assert!(
+            html.starts_with(&format!(
+                "<p><code class=\"math-error\" title=\"{}\">",
+                error
+            )),
+            "{:?}: {}",
+            tex,
+            html
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn mathml_source_lines() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().mathml(true).interactive_tasks(true);
+
+    let html = render(&renderer, "$$\nx\n$$\n\n- [ ] $y$").await?;
+    assert!(html.contains(r#"<li data-source-line="5">"#));
+    assert!(html.contains(r#"<input type="checkbox" data-source-offset="11"/>"#));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn front_matter() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new();
+
+    let markdown = "---\ntitle: Design\ntags: [a, b]\n---\n\n# Heading";
+    assert_eq!(
+        render(&renderer, markdown).await?,
+        "<h1 id=\"heading\" data-source-line=\"6\"><a class=\"anchor\" href=\"#heading\" aria-hidden=\"true\"></a>Heading</h1>\n"
+    );
+
+    let metadata = renderer.metadata(markdown).unwrap();
+    assert_eq!(metadata.title(), Some("Design"));
+    assert_eq!(
+        metadata.get("tags").unwrap(),
+        &serde_json::json!(["a", "b"])
+    );
+
+    let markdown = "+++\ntitle = \"Notes\"\n+++\nText";
+    assert_eq!(
+        render(&renderer, markdown).await?,
+        "<p data-source-line=\"4\">Text</p>\n"
+    );
+    assert_eq!(renderer.metadata(markdown).unwrap().title(), Some("Notes"));
+
+    // TOML datetimes are converted to strings.
+    let markdown = "+++\ndate = 2024-01-02\nupdated = 2024-01-02T03:04:05Z\n+++\n";
+    let metadata = renderer.metadata(markdown).unwrap();
+    assert_eq!(
+        metadata.get("date").unwrap(),
+        &serde_json::json!("2024-01-02")
+    );
+    assert_eq!(
+        metadata.get("updated").unwrap(),
+        &serde_json::json!("2024-01-02T03:04:05Z")
+    );
+
+    // A byte order mark before the front matter is skipped.
+    let markdown = "\u{feff}---\ntitle: Design\n---\nText";
+    assert_eq!(
+        render(&renderer, markdown).await?,
+        "<p data-source-line=\"4\">Text</p>\n"
+    );
+    assert_eq!(renderer.metadata(markdown).unwrap().title(), Some("Design"));
+
+    // The metadata is returned by the render itself.
+    let mut html = String::new();
+    let metadata = renderer
+        .render_document("---\ntitle: Design\n---\nText", &mut html, None)
+        .await?;
+    assert_eq!(html, "<p data-source-line=\"4\">Text</p>\n");
+    assert_eq!(metadata.unwrap().title(), Some("Design"));
+
+    // A setext heading between thematic breaks is not front matter.
+    let markdown = "---\nHeading\n---";
+    assert!(render(&renderer, markdown).await?.contains("<h2"));
+    assert_eq!(renderer.metadata(markdown), None);
+
+    let renderer = MarkdownRenderer::new().front_matter(false);
+    assert_eq!(renderer.metadata("---\ntitle: Design\n---\n"), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn front_matter_table() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .front_matter_table(true);
+
+    let html = render(&renderer, "---\ntitle: Design\ndraft: true\n---\nText").await?;
+    assert_eq!(
+        html,
+        "<table class=\"front-matter\">\n<tbody>\n\
+         <tr><th>draft</th><td>true</td></tr>\n\
+         <tr><th>title</th><td>Design</td></tr>\n\
+         </tbody>\n</table>\n<p>Text</p>\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_front_matter() -> Result<(), Box<dyn Error>> {
+    let renderer = MarkdownRenderer::new().source_lines(false);
+
+    let markdown = "---\ntitle: [unclosed\n---\nText";
+    let html = render(&renderer, markdown).await?;
+    assert!(html.starts_with("<div class=\"block-error\"><p>invalid front matter: "));
+    assert!(html.ends_with("<pre><code>title: [unclosed\n</code></pre></div>\n<p>Text</p>\n"));
+    assert_eq!(renderer.metadata(markdown), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn block_processor_cache() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[derive(Debug)]
+    struct Counter(Arc<AtomicUsize>);
+
+    impl BlockProcessor for Counter {
+        fn process(&self, code: &str, _deadline: Option<Instant>) -> aurelius::Result<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(code.to_uppercase())
+        }
+    }
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let renderer = MarkdownRenderer::new().block_processor("shout", Counter(Arc::clone(&count)));
+
+    let html = render(&renderer, "```shout\nhello\n```").await?;
+    assert_eq!(
+        html,
+        "<div class=\"processed-block\" data-source-line=\"1\">HELLO\n</div>\n"
+    );
+
+    render(&renderer, "# Title\n\n```shout\nhello\n```").await?;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    render(&renderer, "```shout\ngoodbye\n```").await?;
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn block_processor_errors_not_cached() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    /// Fails the first time that it is called.
+    #[derive(Debug, Default)]
+    struct Flaky(AtomicBool);
+
+    impl BlockProcessor for Flaky {
+        fn process(&self, code: &str, _deadline: Option<Instant>) -> aurelius::Result<String> {
+            if self.0.swap(true, Ordering::SeqCst) {
+                Ok(code.to_uppercase())
+            } else {
+                Err(aurelius::Error::RendererTimeout)
+            }
+        }
+    }
+
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .block_processor("shout", Flaky::default());
+
+    let html = render(&renderer, "```shout\nhello\n```").await?;
+    assert!(html.starts_with("<div class=\"block-error\"><p>renderer timed out</p>"));
+
+    let html = render(&renderer, "```shout\nhello\n```").await?;
+    assert_eq!(html, "<div class=\"processed-block\">HELLO\n</div>\n");
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn command_processor() -> Result<(), Box<dyn Error>> {
+    use std::process::Command;
+
+    let mut command = Command::new("sh");
+    command.args([
+        "-c",
+        r#"printf '<?xml version="1.0"?>\n<!DOCTYPE svg>\n<svg>'; cat; printf '</svg>'"#,
+    ]);
+
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .block_processor("svg", CommandProcessor::new(command));
+
+    let html = render(&renderer, "```svg\n<circle/>\n```").await?;
+    assert_eq!(
+        html,
+        "<div class=\"processed-block\"><svg><circle/>\n</svg></div>\n"
+    );
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn command_processor_error() -> Result<(), Box<dyn Error>> {
+    use std::process::Command;
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo 'syntax error' >&2; exit 1"]);
+
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .block_processor("dot", CommandProcessor::new(command));
+
+    let html = render(&renderer, "```dot\ndigraph {\n```\n\nAfter").await?;
+    assert_eq!(
+        html,
+        "<div class=\"block-error\"><p>sh failed with exit status: 1: syntax error</p>\
+         <pre><code>digraph {\n</code></pre></div>\n<p>After</p>\n"
+    );
+
+    let renderer = MarkdownRenderer::new().block_processor(
+        "dot",
+        CommandProcessor::new(Command::new("aurelius-nonexistent-processor")),
+    );
+    assert!(matches!(
+        renderer.validate(),
+        Err(aurelius::Error::ProcessorSpawn(_))
+    ));
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn command_processor_timeout() -> Result<(), Box<dyn Error>> {
+    use std::process::Command;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir()?;
+
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "sleep 1; touch done"])
+        .current_dir(dir.path());
+
+    let processor = CommandProcessor::new(command);
+    let deadline = Instant::now() + Duration::from_millis(100);
+    let err = processor.process("", Some(deadline)).unwrap_err();
+    assert!(matches!(err, aurelius::Error::RendererTimeout));
+
+    // The program was killed before it could finish.
+    thread::sleep(Duration::from_secs(2));
+    assert!(!dir.path().join("done").exists());
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn persistent_renderer() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    for framing in [Framing::LengthPrefixed, Framing::NulDelimited] {
+        let renderer = PersistentRenderer::new(Command::new("cat"), framing);
+
+        assert_eq!(render(&renderer, "Hello, world!").await?, "Hello, world!");
+        assert_eq!(
+            render(&renderer, "Goodbye, world!").await?,
+            "Goodbye, world!"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn persistent_renderer_restarts() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    // Echoes a single document, then exits.
+    let mut command = Command::new("head");
+    command.args(["-z", "-n", "1"]);
+
+    let renderer = PersistentRenderer::new(command, Framing::NulDelimited);
+
+    assert_eq!(render(&renderer, "first").await?, "first");
+    assert_eq!(render(&renderer, "second").await?, "second");
+    assert_eq!(render(&renderer, "third").await?, "third");
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn external_renderer_not_found() {
+    use tokio::process::Command;
+
+    let renderer = ExternalRenderer::new(Command::new("aurelius-nonexistent-renderer"));
+
+    let err = render(&renderer, "Hello, world!").await.unwrap_err();
+    assert!(matches!(
+        *err.downcast::<aurelius::Error>().unwrap(),
+        aurelius::Error::RendererSpawn(_)
+    ));
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn persistent_renderer_survives_cancellation() -> Result<(), Box<dyn Error>> {
+    use futures::FutureExt;
+    use tokio::process::Command;
+
+    let dir = tempfile::tempdir()?;
+
+    // Records each time that the process is spawned.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "echo >> spawns; exec cat"])
+        .current_dir(dir.path());
+
+    let renderer = PersistentRenderer::new(command, Framing::NulDelimited);
+
+    // Cancel a render after it has started, as the server does when newer markdown is sent.
+    let mut html = String::new();
+    let _ = renderer.render("first", &mut html).now_or_never();
+
+    assert_eq!(render(&renderer, "second").await?, "second");
+
+    let spawns = std::fs::read_to_string(dir.path().join("spawns"))?;
+    assert_eq!(spawns.lines().count(), 1);
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn persistent_renderer_timeout() -> Result<(), Box<dyn Error>> {
+    use std::time::{Duration, Instant};
+
+    use tokio::process::Command;
+
+    let dir = tempfile::tempdir()?;
+
+    // Never responds, and records each time that the process is spawned.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "echo >> spawns; exec sleep 10"])
+        .current_dir(dir.path());
+
+    let renderer = PersistentRenderer::new(command, Framing::NulDelimited);
+
+    for _ in 0..2 {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        let err = renderer
+            .render_document("Hello", &mut String::new(), Some(deadline))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, aurelius::Error::RendererTimeout));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    // The process that timed out was killed, so the second render spawned another.
+    let spawns = std::fs::read_to_string(dir.path().join("spawns"))?;
+    assert_eq!(spawns.lines().count(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn persistent_renderer_rejects_nul() {
+    use tokio::process::Command;
+
+    let renderer = PersistentRenderer::new(Command::new("cat"), Framing::NulDelimited);
+
+    assert!(render(&renderer, "\0").await.is_err());
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn external_renderer_exit_error() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo 'something went wrong' >&2; exit 3"]);
+
+    let renderer = ExternalRenderer::new(command);
+
+    let err = render(&renderer, "Hello, world!").await.unwrap_err();
+    let exit_error = match *err.downcast::<aurelius::Error>()? {
+        aurelius::Error::RendererExit(e) => e,
+        e => panic!("unexpected error: {}", e),
+    };
+    assert_eq!(exit_error.code(), Some(3));
+    assert_eq!(exit_error.stderr().trim(), "something went wrong");
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[tokio::test]
+async fn persistent_renderer_exit_error() -> Result<(), Box<dyn Error>> {
+    use tokio::process::Command;
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo 'something went wrong' >&2; exit 3"]);
+
+    let renderer = PersistentRenderer::new(command, Framing::LengthPrefixed);
+
+    let err = render(&renderer, "Hello, world!").await.unwrap_err();
+    let exit_error = match *err.downcast::<aurelius::Error>()? {
+        aurelius::Error::RendererExit(e) => e,
+        e => panic!("unexpected error: {}", e),
+    };
+    assert_eq!(exit_error.code(), Some(3));
+    assert_eq!(exit_error.stderr().trim(), "something went wrong");
+
+    Ok(())
+}
+
+#[cfg(feature = "syntax-highlighting")]
+#[tokio::test]
+async fn syntax_highlighting() -> Result<(), Box<dyn Error>> {
+    use aurelius::HighlightStyle;
+
+    let markdown = "```rust\nfn main() {}\n```\n\n```unknown\n<code>\n```";
+
+    let renderer = MarkdownRenderer::new()
+        .syntax_highlighting(HighlightStyle::Inline(String::from("InspiredGitHub")));
+    renderer.validate()?;
+
+    let html = render(&renderer, markdown).await?;
+    assert!(html.contains(r#"<pre data-source-line="1" style="background-color:#ffffff;">"#));
+    assert!(html.contains(r#"<code class="language-rust" data-highlighted="yes"><span style=""#));
+    assert!(
+        html.contains(r#"<pre data-source-line="5"><code class="language-unknown">&lt;code&gt;"#)
+    );
+
+    let renderer = MarkdownRenderer::new()
+        .source_lines(false)
+        .syntax_highlighting(HighlightStyle::Classed);
+
+    let html = render(&renderer, markdown).await?;
+    assert!(html.contains(r#"<pre><code class="language-rust" data-highlighted="yes"><span class="hl-source hl-rust">"#));
+
+    assert!(aurelius::highlight_css("InspiredGitHub")?.contains(".hl-source"));
+
+    Ok(())
+}
+
+#[cfg(feature = "syntax-highlighting")]
+#[test]
+fn syntax_highlighting_unknown_theme() {
+    use aurelius::{HighlightStyle, Renderer};
+
+    let renderer =
+        MarkdownRenderer::new().syntax_highlighting(HighlightStyle::Inline(String::from("nope")));
+    assert!(matches!(renderer.validate(), Err(aurelius::Error::Theme(theme)) if theme == "nope"));
+
+    assert!(matches!(
+        aurelius::highlight_css("nope"),
+        Err(aurelius::Error::Theme(_))
+    ));
+}